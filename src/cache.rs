@@ -0,0 +1,193 @@
+/*
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.0 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * http://www.opensource.org/licenses/cddl1.txt
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ */
+
+// Yet Another Youtube Down Loader
+// - cache.rs file -
+
+use anyhow::Result;
+use regex::Regex;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Pages with signed links that expire (e.g. "exp=" or "hmac=" tokens in
+// Vimeo's player configuration and HLS playlists) are only used for this
+// long, whatever the TTL is.
+const SIGNED_TTL: Duration = Duration::from_secs(5 * 60);
+
+// An on-disk cache for the pages that the handlers fetch while they
+// look for a video. Every page is stored in its own file, named after
+// the hash of its URL and its variant. Video files are never cached.
+pub struct PageCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl PageCache {
+    pub fn new(dir: &Path, ttl: Duration) -> Self {
+        PageCache {
+            dir: dir.to_path_buf(),
+            ttl,
+        }
+    }
+
+    // Returns the cached page at <url> unless it is older than the TTL.
+    // <variant> describes everything besides the URL that can change the
+    // page (proxy, headers, cookies); pages are only shared between
+    // requests with the same variant.
+    pub fn get(&self, url: &str, variant: &str) -> Option<String> {
+        let file = self.file_for(url, variant);
+        let age = file.metadata().ok()?.modified().ok()?.elapsed().ok()?;
+        if age > self.ttl {
+            return None;
+        }
+
+        // The first line is the URL, in case of hash collisions.
+        let contents = fs::read_to_string(file).ok()?;
+        let (cached_url, body) = contents.split_once('\n')?;
+        if cached_url != url {
+            return None;
+        }
+        if age > SIGNED_TTL && has_signed_links(&contents) {
+            return None;
+        }
+        Some(body.to_string())
+    }
+
+    pub fn store(&self, url: &str, variant: &str, body: &str) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.file_for(url, variant), format!("{}\n{}", url, body))?;
+        Ok(())
+    }
+
+    fn file_for(&self, url: &str, variant: &str) -> PathBuf {
+        let key = format!("{}\n{}", url, variant);
+        self.dir
+            .join(format!("{:016x}.page", fnv1a(key.as_bytes())))
+    }
+}
+
+// Whether <text> (a URL or a page) contains links that are signed or
+// expire after a while.
+fn has_signed_links(text: &str) -> bool {
+    Regex::new(r"(?i)(?:[?&/~;]|\\u0026|&amp;)(?:exp|expire|expires|x-amz-expires|hmac|signature)=")
+        .unwrap()
+        .is_match(text)
+}
+
+// Deletes the cache directory with everything in it.
+pub fn remove_cache_dir(dir: &Path) -> Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+// The file names must not change between two builds of yaydl, so
+// Rust's DefaultHasher is out. FNV-1a is simple and good enough here.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn cache(name: &str, ttl: Duration) -> PageCache {
+        let dir = std::env::temp_dir().join(format!("yaydl-cache-{}-{}", std::process::id(), name));
+        PageCache::new(&dir, ttl)
+    }
+
+    // Makes the cached page look <age> old.
+    fn age_page(cache: &PageCache, url: &str, variant: &str, age: Duration) {
+        let file = fs::File::options()
+            .write(true)
+            .open(cache.file_for(url, variant))
+            .unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_variants_apart() {
+        let cache = cache("variants", Duration::from_secs(3600));
+        let url = "https://cache.test/video/1";
+        assert_eq!(cache.file_for(url, "a"), cache.file_for(url, "a"));
+        assert_ne!(cache.file_for(url, "a"), cache.file_for(url, "b"));
+        assert_ne!(
+            cache.file_for(url, "a"),
+            cache.file_for("https://cache.test/video/2", "a")
+        );
+
+        cache.store(url, "a", "page a").unwrap();
+        cache.store(url, "b", "page b").unwrap();
+        assert_eq!(cache.get(url, "a").as_deref(), Some("page a"));
+        assert_eq!(cache.get(url, "b").as_deref(), Some("page b"));
+        assert_eq!(cache.get(url, "c"), None);
+
+        // The file starts with the URL, so that hash collisions are noticed:
+        let contents = fs::read_to_string(cache.file_for(url, "a")).unwrap();
+        assert_eq!(contents, "https://cache.test/video/1\npage a");
+        fs::write(cache.file_for(url, "a"), "https://cache.test/other\npage a").unwrap();
+        assert_eq!(cache.get(url, "a"), None);
+
+        remove_cache_dir(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn expires_pages_after_the_ttl() {
+        let cache = cache("ttl", Duration::from_secs(3600));
+        let url = "https://cache.test/video/1";
+        cache.store(url, "", "page").unwrap();
+
+        age_page(&cache, url, "", Duration::from_secs(3000));
+        assert_eq!(cache.get(url, "").as_deref(), Some("page"));
+        age_page(&cache, url, "", Duration::from_secs(4000));
+        assert_eq!(cache.get(url, ""), None);
+
+        remove_cache_dir(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn expires_signed_links_early() {
+        let cache = cache("signed", Duration::from_secs(3600));
+        let config = "https://player.test/video/1/config";
+        let playlist = "https://cdn.test/exp=1760803600~hmac=4f0e/1/playlist.m3u8";
+        cache
+            .store(
+                config,
+                "",
+                r#"{"url":"https://cdn.test/1.mp4?expires=1760803600\u0026signature=ab"}"#,
+            )
+            .unwrap();
+        cache.store(playlist, "", "#EXTM3U").unwrap();
+
+        assert!(cache.get(config, "").is_some());
+        assert!(cache.get(playlist, "").is_some());
+        for url in [config, playlist] {
+            age_page(&cache, url, "", Duration::from_secs(600));
+            assert_eq!(cache.get(url, ""), None, "{}", url);
+        }
+
+        assert!(!has_signed_links("https://cache.test/watch?v=1&export=0"));
+        assert!(!has_signed_links("<a href=\"/explore\">Explore</a>"));
+
+        remove_cache_dir(&cache.dir).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+/*
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.0 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * http://www.opensource.org/licenses/cddl1.txt
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ */
+
+// Yet Another Youtube Down Loader
+// - cookies.rs file -
+
+use anyhow::{anyhow, Result};
+use cookie_store::{CookieDomain, CookieExpiration, CookieStore, RawCookie};
+use std::{
+    fs,
+    path::Path,
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use ureq::{
+    http::{header, HeaderValue, Request, Response},
+    middleware::{Middleware, MiddlewareNext},
+    Body, ResponseExt, SendBody,
+};
+use url::Url;
+
+// All agents share the same cookies.
+static COOKIE_JAR: LazyLock<Mutex<CookieStore>> =
+    LazyLock::new(|| Mutex::new(CookieStore::default()));
+
+// Cookie files are stored in the Netscape format (as used by curl and
+// most browser extensions):
+//   domain  include_subdomains  path  secure  expiry  name  value
+// HttpOnly cookies are prefixed with "#HttpOnly_".
+const HTTPONLY_PREFIX: &str = "#HttpOnly_";
+
+// Analytics, load balancers and bot protection set new values for these
+// all the time, but they do not change the pages.
+const VOLATILE_COOKIES: [&str; 9] = [
+    "_ga", "_gat", "_gid", "_fbp", "__cf_bm", "__cfduid", "_cfuvid", "YSC", "vuid",
+];
+const VOLATILE_COOKIE_PREFIXES: [&str; 5] = ["_ga_", "_gat_", "__utm", "_pk_", "AWSALB"];
+
+// Cookies that expire within this many seconds are rotated, too.
+const SHORT_LIVED_COOKIE: i64 = 60 * 60;
+
+pub fn load_cookies(cookiefile: &Path) -> Result<()> {
+    let contents = fs::read_to_string(cookiefile)?;
+    let mut jar = COOKIE_JAR.lock().unwrap();
+
+    for (num, line) in contents.lines().enumerate() {
+        let (line, http_only) = match line.strip_prefix(HTTPONLY_PREFIX) {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return Err(anyhow!(
+                "{}:{}: not a Netscape cookie line.",
+                cookiefile.display(),
+                num + 1
+            ));
+        }
+        let [domain, subdomains, path, secure, expiry, name, value] = fields[..] else {
+            unreachable!()
+        };
+
+        let host = domain.trim_start_matches('.');
+        let secure = secure.eq_ignore_ascii_case("TRUE");
+        let mut cookie = RawCookie::build((name.to_string(), value.to_string()))
+            .path(path.to_string())
+            .secure(secure)
+            .http_only(http_only);
+
+        // Cookies without a Domain attribute only apply to their exact host:
+        if subdomains.eq_ignore_ascii_case("TRUE") {
+            cookie = cookie.domain(host.to_string());
+        }
+
+        // An expiry of 0 marks a session cookie.
+        let expiry: i64 = expiry.parse().unwrap_or(0);
+        if expiry > 0 {
+            let expires = cookie::time::OffsetDateTime::from_unix_timestamp(expiry)?;
+            cookie = cookie.expires(expires);
+        }
+
+        let origin = Url::parse(&format!(
+            "{}://{}{}",
+            if secure { "https" } else { "http" },
+            host,
+            path
+        ))?;
+        // Expired cookies are silently skipped.
+        let _ = jar.insert_raw(&cookie.build(), &origin);
+    }
+
+    Ok(())
+}
+
+pub fn save_cookies(cookiefile: &Path) -> Result<()> {
+    let jar = COOKIE_JAR.lock().unwrap();
+
+    let mut contents = String::from("# Netscape HTTP Cookie File\n# Written by yaydl.\n\n");
+    for cookie in jar.iter_unexpired() {
+        let (domain, subdomains) = match &cookie.domain {
+            CookieDomain::HostOnly(host) => (host.to_string(), "FALSE"),
+            CookieDomain::Suffix(suffix) => (format!(".{}", suffix), "TRUE"),
+            _ => continue,
+        };
+        let expiry = match &cookie.expires {
+            CookieExpiration::AtUtc(time) => time.unix_timestamp(),
+            CookieExpiration::SessionEnd => 0,
+        };
+
+        contents.push_str(&format!(
+            "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            if cookie.http_only().unwrap_or(false) {
+                HTTPONLY_PREFIX
+            } else {
+                ""
+            },
+            domain,
+            subdomains,
+            String::from(&cookie.path),
+            if cookie.secure().unwrap_or(false) {
+                "TRUE"
+            } else {
+                "FALSE"
+            },
+            expiry,
+            cookie.name(),
+            cookie.value()
+        ));
+    }
+
+    fs::write(cookiefile, contents)?;
+    Ok(())
+}
+
+// Returns the cookies that a request to <url> sends, as in the Cookie header.
+pub fn request_cookies(url: &Url) -> String {
+    COOKIE_JAR
+        .lock()
+        .unwrap()
+        .get_request_values(url)
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// Like request_cookies(), but without the cookies that change from one
+// request to the next, so the page cache can tell logged-in sessions
+// apart without missing every time.
+pub fn page_cookies(url: &Url) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    COOKIE_JAR
+        .lock()
+        .unwrap()
+        .matches(url)
+        .into_iter()
+        .filter(|cookie| {
+            let name = cookie.name();
+            let short_lived = match &cookie.expires {
+                CookieExpiration::AtUtc(time) => time.unix_timestamp() - now < SHORT_LIVED_COOKIE,
+                CookieExpiration::SessionEnd => false,
+            };
+            !short_lived
+                && !VOLATILE_COOKIES.contains(&name)
+                && !VOLATILE_COOKIE_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+        })
+        .map(|cookie| format!("{}={}", cookie.name(), cookie.value()))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// Sends the matching cookies with every request and stores the ones that
+// the server sets in return.
+pub struct CookieMiddleware;
+impl Middleware for CookieMiddleware {
+    fn handle(
+        &self,
+        mut request: Request<SendBody>,
+        next: MiddlewareNext,
+    ) -> Result<Response<Body>, ureq::Error> {
+        let Ok(url) = Url::parse(&request.uri().to_string()) else {
+            return next.handle(request);
+        };
+
+        let cookies = request_cookies(&url);
+        if !cookies.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&cookies) {
+                request.headers_mut().insert(header::COOKIE, value);
+            }
+        }
+
+        let response = next.handle(request)?;
+
+        // We might have been redirected in between:
+        let url = Url::parse(&response.get_uri().to_string()).unwrap_or(url);
+        let set_cookies = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .filter_map(|h| RawCookie::parse(h.to_string()).ok());
+        COOKIE_JAR
+            .lock()
+            .unwrap()
+            .store_response_cookies(set_cookies, &url);
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("yaydl-cookies-{}-{}", std::process::id(), name))
+    }
+
+    fn cookies_for(url: &str) -> String {
+        request_cookies(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn loads_and_saves_netscape_cookie_files() {
+        let cookiefile = temp_file("cookies.txt");
+        fs::write(
+            &cookiefile,
+            "# Netscape HTTP Cookie File\n\
+             \n\
+             .cookies.test\tTRUE\t/\tFALSE\t0\tsession\tone\n\
+             #HttpOnly_secure.cookies.test\tFALSE\t/account\tTRUE\t4102444800\ttoken\ttwo\n\
+             .cookies.test\tTRUE\t/\tFALSE\t1000000\told\tgone\n",
+        )
+        .unwrap();
+        load_cookies(&cookiefile).unwrap();
+
+        assert_eq!(cookies_for("http://www.cookies.test/"), "session=one");
+        // The token is only sent over HTTPS, to its host and path:
+        assert_eq!(
+            cookies_for("http://secure.cookies.test/account/"),
+            "session=one"
+        );
+        assert_eq!(
+            cookies_for("https://www.cookies.test/account/"),
+            "session=one"
+        );
+        let secure = cookies_for("https://secure.cookies.test/account/settings");
+        assert!(secure.contains("session=one"));
+        assert!(secure.contains("token=two"));
+
+        save_cookies(&cookiefile).unwrap();
+        let saved = fs::read_to_string(&cookiefile).unwrap();
+        assert!(saved.starts_with("# Netscape HTTP Cookie File\n"));
+        assert!(saved
+            .lines()
+            .any(|line| line == ".cookies.test\tTRUE\t/\tFALSE\t0\tsession\tone"));
+        assert!(saved.lines().any(|line| line
+            == "#HttpOnly_secure.cookies.test\tFALSE\t/account\tTRUE\t4102444800\ttoken\ttwo"));
+        assert!(!saved.contains("gone"));
+        fs::remove_file(&cookiefile).unwrap();
+    }
+
+    #[test]
+    fn leaves_the_volatile_cookies_out_of_the_pages() {
+        let cookiefile = temp_file("volatile.txt");
+        let soon = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 600;
+        fs::write(
+            &cookiefile,
+            format!(
+                ".pages.test\tTRUE\t/\tFALSE\t0\tsession\tone\n\
+                 .pages.test\tTRUE\t/\tFALSE\t4102444800\t_ga_ABC123\tGS1.1\n\
+                 .pages.test\tTRUE\t/\tFALSE\t0\t__cf_bm\tx1\n\
+                 .pages.test\tTRUE\t/\tFALSE\t4102444800\t_gallery\tgrid\n\
+                 .pages.test\tTRUE\t/\tFALSE\t{}\trotating\tr1\n",
+                soon
+            ),
+        )
+        .unwrap();
+        load_cookies(&cookiefile).unwrap();
+        fs::remove_file(&cookiefile).unwrap();
+
+        let url = Url::parse("https://www.pages.test/").unwrap();
+        let mut sent: Vec<_> = request_cookies(&url)
+            .split("; ")
+            .map(String::from)
+            .collect();
+        sent.sort();
+        assert_eq!(
+            sent,
+            [
+                "__cf_bm=x1",
+                "_ga_ABC123=GS1.1",
+                "_gallery=grid",
+                "rotating=r1",
+                "session=one"
+            ]
+        );
+        let mut kept: Vec<_> = page_cookies(&url).split("; ").map(String::from).collect();
+        kept.sort();
+        assert_eq!(kept, ["_gallery=grid", "session=one"]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let cookiefile = temp_file("broken.txt");
+        fs::write(&cookiefile, "# comment\nname=value\n").unwrap();
+        let error = load_cookies(&cookiefile).unwrap_err().to_string();
+        assert!(error.ends_with(":2: not a Netscape cookie line."));
+        fs::remove_file(&cookiefile).unwrap();
+
+        assert!(load_cookies(&temp_file("missing.txt")).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.0 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * http://www.opensource.org/licenses/cddl1.txt
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ */
+
+// Yet Another Youtube Down Loader
+// - credentials.rs file -
+
+use anyhow::{anyhow, Result};
+use std::{env, fs, path::PathBuf};
+
+// The account to log in with, from --username/--password or --netrc.
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+// The .netrc file: $NETRC or ~/.netrc (~/_netrc on Windows).
+fn netrc_file() -> Option<PathBuf> {
+    if let Some(netrc) = env::var_os("NETRC") {
+        return Some(PathBuf::from(netrc));
+    }
+
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    let name = if cfg!(windows) { "_netrc" } else { ".netrc" };
+    Some(PathBuf::from(home).join(name))
+}
+
+// One "machine" (or "default") entry of a .netrc file.
+struct NetrcEntry {
+    machine: Option<String>,
+    login: String,
+    password: String,
+}
+
+// .netrc files are just whitespace-separated tokens:
+// machine <host> login <username> password <password> ...
+fn parse_netrc(contents: &str) -> Vec<NetrcEntry> {
+    let mut entries: Vec<NetrcEntry> = vec![];
+    let mut tokens = contents.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match (token, entries.last_mut()) {
+            ("machine", _) => entries.push(NetrcEntry {
+                machine: tokens.next().map(str::to_string),
+                login: String::new(),
+                password: String::new(),
+            }),
+            ("default", _) => entries.push(NetrcEntry {
+                machine: None,
+                login: String::new(),
+                password: String::new(),
+            }),
+            ("login", Some(entry)) => entry.login = tokens.next().unwrap_or("").to_string(),
+            ("password", Some(entry)) => entry.password = tokens.next().unwrap_or("").to_string(),
+            ("account" | "macdef", _) => {
+                // Not needed here.
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+// Finds the credentials for <host> in the .netrc file. "www." is ignored,
+// so "machine vimeo.com" works for www.vimeo.com, too. The "default"
+// entry is used if there is no matching machine.
+pub fn from_netrc(host: &str) -> Result<Option<Credentials>> {
+    let file = netrc_file().ok_or_else(|| anyhow!("Could not find your .netrc file."))?;
+    let contents = fs::read_to_string(&file)
+        .map_err(|e| anyhow!("Could not read {}: {}", file.display(), e))?;
+    let entries = parse_netrc(&contents);
+
+    let host = host.trim_start_matches("www.");
+    let entry = entries
+        .iter()
+        .find(|entry| {
+            entry
+                .machine
+                .as_ref()
+                .is_some_and(|machine| machine.trim_start_matches("www.") == host)
+        })
+        .or_else(|| entries.iter().find(|entry| entry.machine.is_none()));
+
+    Ok(entry.map(|entry| Credentials {
+        username: entry.login.to_string(),
+        password: entry.password.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_netrc_files() {
+        let entries = parse_netrc(
+            "machine vimeo.com login alice password s3cret\n\
+             machine www.example.com\n\
+             \tlogin bob\n\
+             \taccount ignored\n\
+             \tpassword hunter2\n\
+             default login guest password guest\n",
+        );
+
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|e| (e.machine.as_deref(), e.login.as_str(), e.password.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (Some("vimeo.com"), "alice", "s3cret"),
+                (Some("www.example.com"), "bob", "hunter2"),
+                (None, "guest", "guest"),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_tokens_outside_of_entries() {
+        let entries = parse_netrc("login nobody password nothing machine vimeo.com login");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].machine.as_deref(), Some("vimeo.com"));
+        assert_eq!(entries[0].login, "");
+        assert!(parse_netrc("").is_empty());
+    }
+}
@@ -17,15 +17,125 @@
 // - definitions.rs file -
 
 use anyhow::Result;
+use scraper::{Html, Selector};
 
+use crate::agent::YaydlAgent;
+use crate::credentials::Credentials;
 use crate::VIDEO;
 
+// Optional information about a video (for --embed-metadata).
+#[derive(Default)]
+pub struct VideoMetadata {
+    pub uploader: Option<String>,
+    // YYYY-MM-DD
+    pub upload_date: Option<String>,
+    pub description: Option<String>,
+    // The URL of the thumbnail image.
+    pub thumbnail: Option<String>,
+    // In seconds.
+    pub duration: Option<u64>,
+    pub subtitles: Vec<Subtitle>,
+}
+
+// A subtitle track of a video.
+pub struct Subtitle {
+    // e.g. "en"
+    pub language: String,
+    // e.g. "English (auto-generated)"
+    pub label: String,
+    pub url: String,
+}
+
+impl VideoMetadata {
+    // Reads the usual OpenGraph and schema.org tags from a video page.
+    pub fn from_html(html: &Html) -> Self {
+        let find = |selectors: &[&str]| {
+            selectors.iter().find_map(|selector| {
+                let selector = Selector::parse(selector).unwrap();
+                let element = html.select(&selector).next()?.value();
+                let content = element.attr("content").or(element.attr("href"))?;
+                let content = content.trim();
+                (!content.is_empty()).then(|| content.to_string())
+            })
+        };
+
+        VideoMetadata {
+            uploader: find(&[r#"meta[itemprop="author"]"#, r#"meta[name="author"]"#]),
+            upload_date: find(&[
+                r#"meta[itemprop="uploadDate"]"#,
+                r#"meta[property="video:release_date"]"#,
+            ])
+            .and_then(|date| Self::to_date(&date)),
+            description: find(&[
+                r#"meta[property="og:description"]"#,
+                r#"meta[name="description"]"#,
+            ]),
+            thumbnail: find(&[
+                r#"meta[property="og:image"]"#,
+                r#"meta[name="twitter:image"]"#,
+                r#"link[itemprop="thumbnailUrl"]"#,
+            ]),
+            ..VideoMetadata::default()
+        }
+    }
+
+    // Turns a Unix timestamp into YYYY-MM-DD.
+    pub fn date_from_timestamp(timestamp: i64) -> String {
+        // Days since 1970-01-01 to a civil date, see
+        // http://howardhinnant.github.io/date_algorithms.html
+        let days = timestamp.div_euclid(86400) + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    // Turns an ISO 8601 date (and time) into YYYY-MM-DD.
+    pub fn to_date(date: &str) -> Option<String> {
+        let date = date.get(..10)?;
+        let valid = date.chars().enumerate().all(|(i, c)| {
+            if i == 4 || i == 7 {
+                c == '-'
+            } else {
+                c.is_ascii_digit()
+            }
+        });
+        valid.then(|| date.to_string())
+    }
+}
+
+// One stream of a video whose video and audio are served separately.
+pub struct Stream {
+    pub url: String,
+    // The file extension, e.g. "mp4", "webm" or "m4a".
+    pub ext: String,
+    // true, if <url> is an HLS playlist.
+    pub is_playlist: bool,
+}
+
+// A video-only and an audio-only stream that need to be merged.
+pub struct StreamPair {
+    pub video: Stream,
+    pub audio: Stream,
+}
+
 // Define the public interface for site definitions:
 pub trait SiteDefinition: Sync + Send {
     // true, if this site can handle <url>.
     fn can_handle_url<'a>(
         &'a self,
         video: &mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool>;
@@ -34,6 +144,7 @@ pub trait SiteDefinition: Sync + Send {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
     ) -> Result<bool>;
@@ -45,6 +156,7 @@ pub trait SiteDefinition: Sync + Send {
     fn find_video_title<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
     ) -> Result<String>;
@@ -53,23 +165,128 @@ pub trait SiteDefinition: Sync + Send {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
         onlyaudio: bool,
     ) -> Result<String>;
 
+    // returns separate video and audio streams if the site has them in
+    // a better quality than the combined ones. They will be merged.
+    fn find_stream_pair<'a>(
+        &'a self,
+        _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
+        _url: &'a str,
+        _webdriver_port: u16,
+    ) -> Result<Option<StreamPair>> {
+        Ok(None)
+    }
+
+    // returns the URLs of the videos in a playlist, or None if <url> is
+    // a single video. <no_playlist> is true if only the video should be
+    // downloaded from a URL that refers to both.
+    fn find_playlist_entries<'a>(
+        &'a self,
+        _agent: &YaydlAgent,
+        _url: &'a str,
+        _no_playlist: bool,
+    ) -> Result<Option<Vec<String>>> {
+        Ok(None)
+    }
+
     // returns the file extension of the video (e.g. "mp4").
     fn find_video_file_extension<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
         onlyaudio: bool,
     ) -> Result<String>;
 
     // returns the name of the site (e.g. "YouTube").
-    fn display_name<'a>(&'a self) -> String;
+    fn display_name(&self) -> String;
 
     // true, if this site needs a web driver.
-    fn web_driver_required<'a>(&'a self) -> bool;
+    fn web_driver_required(&self) -> bool;
+
+    // returns site-specific HTTP headers (e.g. a Referer) for downloading
+    // the video from <url>. Command-line options take precedence.
+    fn http_headers(&self, _url: &str) -> Vec<(String, String)> {
+        vec![]
+    }
+
+    // returns what else is known about the video (for --embed-metadata).
+    fn find_video_metadata<'a>(
+        &'a self,
+        _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
+        _url: &'a str,
+        _webdriver_port: u16,
+    ) -> Result<VideoMetadata> {
+        Ok(VideoMetadata::default())
+    }
+
+    // logs in with <credentials>. The session cookies are kept by the
+    // agent and used for everything that follows. false, if the site
+    // has no logins.
+    fn login(&self, _agent: &YaydlAgent, _credentials: &Credentials) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortens_iso_dates() {
+        assert_eq!(
+            VideoMetadata::to_date("2023-07-14T18:30:00+02:00"),
+            Some("2023-07-14".to_string())
+        );
+        assert_eq!(
+            VideoMetadata::to_date("2001-02-03"),
+            Some("2001-02-03".to_string())
+        );
+        assert_eq!(VideoMetadata::to_date("14.07.2023"), None);
+        assert_eq!(VideoMetadata::to_date("2023-7-14"), None);
+        assert_eq!(VideoMetadata::to_date("yesterday"), None);
+    }
+
+    #[test]
+    fn converts_timestamps_to_dates() {
+        assert_eq!(VideoMetadata::date_from_timestamp(0), "1970-01-01");
+        assert_eq!(VideoMetadata::date_from_timestamp(1161216000), "2006-10-19");
+        // Leap days and the end of a day:
+        assert_eq!(VideoMetadata::date_from_timestamp(951782400), "2000-02-29");
+        assert_eq!(VideoMetadata::date_from_timestamp(1709251199), "2024-02-29");
+        assert_eq!(VideoMetadata::date_from_timestamp(-86400), "1969-12-31");
+    }
+
+    #[test]
+    fn reads_the_metadata_from_a_page() {
+        let html = Html::parse_document(
+            r#"<html><head>
+            <meta name="author" content="  Jane Doe ">
+            <meta property="video:release_date" content="2022-12-24T20:00:00Z">
+            <meta property="og:description" content="">
+            <meta name="description" content="A quiet evening.">
+            <link itemprop="thumbnailUrl" href="https://example.com/thumb.jpg">
+            </head></html>"#,
+        );
+        let metadata = VideoMetadata::from_html(&html);
+        assert_eq!(metadata.uploader.as_deref(), Some("Jane Doe"));
+        assert_eq!(metadata.upload_date.as_deref(), Some("2022-12-24"));
+        // Empty tags are skipped:
+        assert_eq!(metadata.description.as_deref(), Some("A quiet evening."));
+        assert_eq!(
+            metadata.thumbnail.as_deref(),
+            Some("https://example.com/thumb.jpg")
+        );
+
+        let metadata = VideoMetadata::from_html(&Html::parse_document("<p>Nothing.</p>"));
+        assert!(metadata.uploader.is_none() && metadata.upload_date.is_none());
+    }
 }
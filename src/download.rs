@@ -16,17 +16,26 @@
 // Yet Another Youtube Down Loader
 // - download.rs file -
 
-use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
+use anyhow::{anyhow, Result};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use m3u8_rs::MediaSegment;
 use nom::Finish;
 use std::{
     fs,
     io::{self, copy, Read},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    thread,
+    time::Duration,
 };
+use ureq::{typestate::WithoutBody, RequestBuilder};
 use url::Url;
 
-use crate::agent::{AgentBase, YaydlAgent};
+use crate::agent::YaydlAgent;
+use crate::definitions::{Stream, StreamPair};
+
+// Parallel downloads get one progress bar each.
+static PROGRESS_BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
 
 struct DownloadProgress<'a, R> {
     inner: R,
@@ -35,23 +44,290 @@ struct DownloadProgress<'a, R> {
 
 impl<R: Read> Read for DownloadProgress<'_, R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.read(buf).map(|n| {
+        self.inner.read(buf).inspect(|&n| {
             self.progress_bar.inc(n as u64);
-            n
         })
     }
 }
 
-pub fn download_from_playlist(url: &str, filename: &str, verbose: bool) -> Result<()> {
+// Both single-file and playlist downloads share the same progress bar.
+fn progress_bar(total_size: u64) -> ProgressBar {
+    let pb = PROGRESS_BARS.add(ProgressBar::new(total_size));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.green/blue}] {percent}% ({bytes}/{total_bytes}, {bytes_per_sec}, ETA {eta})",
+        )
+        .unwrap()
+        .progress_chars("#>-"),
+    );
+    pb
+}
+
+// The same for ffmpeg, which counts milliseconds of media instead of
+// bytes. Without a <duration>, only the processed time is shown.
+pub fn conversion_progress_bar(duration: Option<Duration>) -> ProgressBar {
+    let template = if duration.is_some() {
+        "{spinner:.green} [{elapsed_precise}] [{bar:40.green/blue}] {percent}% ({msg}, ETA {eta})"
+    } else {
+        "{spinner:.green} [{elapsed_precise}] ({msg})"
+    };
+    let duration_ms = duration.map_or(0, |d| d.as_millis() as u64);
+    let pb = PROGRESS_BARS.add(ProgressBar::new(duration_ms));
+    pb.set_style(
+        ProgressStyle::with_template(template)
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pb
+}
+
+// Playlists do not tell us their size in bytes. Extrapolate it from the
+// bytes per second of media we have seen so far.
+fn estimate_playlist_size(known_bytes: u64, known_duration: f64, total_duration: f64) -> u64 {
+    if known_duration <= 0.0 {
+        return known_bytes;
+    }
+    (known_bytes as f64 / known_duration * total_duration) as u64
+}
+
+// Returns the handler's <headers> for the downloads. Unless the handler
+// wants something else, the Referer is the page the video is on.
+pub fn with_page_referer(
+    mut headers: Vec<(String, String)>,
+    page_url: &str,
+) -> Vec<(String, String)> {
+    if !headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("Referer"))
+    {
+        headers.push(("Referer".to_string(), page_url.to_string()));
+    }
+    headers
+}
+
+// Adds the handler's headers to <request>.
+fn with_headers(
+    mut request: RequestBuilder<WithoutBody>,
+    headers: &[(String, String)],
+) -> RequestBuilder<WithoutBody> {
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    request
+}
+
+// A time range of the video (as in --download-sections), in seconds.
+#[derive(Clone, Copy)]
+pub struct Section {
+    pub start: f64,
+    // None means "until the end".
+    pub end: Option<f64>,
+}
+
+impl Section {
+    pub fn duration(&self) -> Option<f64> {
+        self.end.map(|end| end - self.start)
+    }
+}
+
+// Parses [[HH:]MM:]SS[.ms] into seconds.
+fn parse_time(time: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for (num, part) in time.split(':').enumerate() {
+        let value: f64 = part.parse().ok()?;
+        if num > 2 || !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some(seconds)
+}
+
+// Parses a time range like "*10:00-15:30". The end can be "inf".
+pub fn parse_section(section: &str) -> Result<Section, String> {
+    let invalid = || {
+        format!(
+            "\"{}\" is not a valid time range, use something like \"*10:00-15:30\".",
+            section
+        )
+    };
+
+    let (start, end) = section
+        .trim_start_matches('*')
+        .split_once('-')
+        .ok_or_else(invalid)?;
+    let start = parse_time(start).ok_or_else(invalid)?;
+    let end = match end {
+        "inf" | "" => None,
+        end => Some(parse_time(end).ok_or_else(invalid)?),
+    };
+    if end.is_some_and(|end| end <= start) {
+        return Err(invalid());
+    }
+    Ok(Section { start, end })
+}
+
+// Segments between two #EXT-X-DISCONTINUITY tags share one timeline.
+// Some sites splice ads into their streams that way.
+struct SegmentGroup<'a> {
+    segments: Vec<&'a MediaSegment>,
+    looks_like_ad: bool,
+}
+
+// Returns the "directory" a segment is served from, e.g.
+// https://foo.bar/play/ for https://foo.bar/play/file1.ts.
+fn segment_origin(segment_url: &Url) -> String {
+    let mut origin = segment_url.clone();
+    origin.set_query(None);
+    if let Ok(mut path) = origin.path_segments_mut() {
+        path.pop();
+    }
+    origin.to_string()
+}
+
+// The words in an EXT-X-DATERANGE class (e.g. "com.example.ad-break")
+// that mark an ad.
+const AD_CLASS_TOKENS: [&str; 6] = [
+    "ad",
+    "ads",
+    "adbreak",
+    "advert",
+    "advertisement",
+    "interstitial",
+];
+
+// The EXT-X-DATERANGE attributes that carry SCTE-35 cues.
+const SCTE35_ATTRIBUTES: [&str; 3] = ["SCTE35-CMD", "SCTE35-OUT", "SCTE35-IN"];
+
+// Ad breaks are usually announced with SCTE-35 cues or come from
+// a different server than the actual video.
+fn has_ad_markers(segment: &MediaSegment) -> bool {
+    let cue_tag = segment
+        .unknown_tags
+        .iter()
+        .any(|t| t.tag.starts_with("X-CUE") || t.tag.starts_with("X-SCTE35"));
+    let ad_daterange = segment.daterange.as_ref().is_some_and(|d| {
+        d.class.as_deref().is_some_and(|class| {
+            class
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|token| {
+                    AD_CLASS_TOKENS
+                        .iter()
+                        .any(|t| token.eq_ignore_ascii_case(t))
+                })
+        }) || d
+            .other_attributes
+            .as_ref()
+            .is_some_and(|a| a.keys().any(|k| SCTE35_ATTRIBUTES.contains(&k.as_str())))
+    });
+    cue_tag || ad_daterange
+}
+
+fn group_segments<'a>(playlist_url: &Url, segments: &'a [MediaSegment]) -> Vec<SegmentGroup<'a>> {
+    let mut groups: Vec<SegmentGroup> = vec![];
+    for segment in segments {
+        if segment.discontinuity || groups.is_empty() {
+            groups.push(SegmentGroup {
+                segments: vec![],
+                looks_like_ad: false,
+            });
+        }
+        groups.last_mut().unwrap().segments.push(segment);
+    }
+
+    if groups.len() < 2 {
+        return groups;
+    }
+
+    // The longest group is the video. Everything else is compared to it.
+    let group_duration = |g: &SegmentGroup| g.segments.iter().map(|s| s.duration).sum::<f32>();
+    let main_idx = (0..groups.len())
+        .max_by(|a, b| group_duration(&groups[*a]).total_cmp(&group_duration(&groups[*b])))
+        .unwrap();
+    let main_origin = playlist_url
+        .join(&groups[main_idx].segments[0].uri)
+        .map(|u| segment_origin(&u))
+        .unwrap_or_default();
+
+    for (idx, group) in groups.iter_mut().enumerate() {
+        if idx == main_idx {
+            continue;
+        }
+        let foreign_origin = group.segments.iter().any(|s| {
+            playlist_url
+                .join(&s.uri)
+                .map(|u| segment_origin(&u) != main_origin)
+                .unwrap_or(false)
+        });
+        group.looks_like_ad = foreign_origin || group.segments.iter().any(|s| has_ad_markers(s));
+    }
+
+    groups
+}
+
+// Returns the file name for the <num>th part of <filename>,
+// e.g. "video.part1.ts" for "video.ts".
+fn part_file_name(filename: &str, num: usize) -> PathBuf {
+    let file = Path::new(filename);
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = file.extension().unwrap_or_default().to_string_lossy();
+    file.with_file_name(format!("{}.part{}.{}", stem, num, ext))
+}
+
+// Drops the segments outside of <section> from <groups>. Returns where
+// the first remaining segment starts (in seconds) and the EXT-X-MAP URI
+// that applies to it.
+fn select_section<'a>(
+    groups: &mut Vec<SegmentGroup<'a>>,
+    section: &Section,
+) -> (f64, Option<&'a str>) {
+    let mut time = 0.0;
+    let mut first_time = None;
+    let mut map_uri = None;
+    let mut first_map_uri = None;
+    for group in groups.iter_mut() {
+        group.segments.retain(|segment| {
+            if let Some(map) = &segment.map {
+                map_uri = Some(map.uri.as_str());
+            }
+            let segment_start = time;
+            time += segment.duration as f64;
+
+            let keep = time > section.start && section.end.is_none_or(|end| segment_start < end);
+            if keep && first_time.is_none() {
+                first_time = Some(segment_start);
+                first_map_uri = map_uri;
+            }
+            keep
+        });
+    }
+    groups.retain(|g| !g.segments.is_empty());
+
+    (first_time.unwrap_or(0.0), first_map_uri)
+}
+
+// Downloads the playlist at <url> and returns the file(s) it was stored in.
+// Playlists with discontinuities are split into one file per segment group
+// so they can be remuxed separately.
+// With a <section>, only the segments that cover it are downloaded. The
+// second return value is where the first file starts then (in seconds).
+pub fn download_from_playlist(
+    agent: &YaydlAgent,
+    url: &str,
+    filename: &str,
+    headers: &[(String, String)],
+    skip_ads: bool,
+    section: Option<&Section>,
+    verbose: bool,
+) -> Result<(Vec<PathBuf>, f64)> {
     // Download the playlist file into the temporary directory:
     if verbose {
-        println!("{}", "Found a playlist. Fetching ...");
+        println!("Found a playlist. Fetching ...");
     }
 
-    let mut url = Url::parse(url)?;
-    let agent = YaydlAgent::init(url.clone());
+    let url = Url::parse(url)?;
 
-    let request = agent.get(url.as_str()).header("Referer", url.as_str());
+    let request = with_headers(agent.get(url.as_str()), headers);
     let playlist_text = request
         .call()
         .expect("Could not go to the playlist url")
@@ -60,84 +336,159 @@ pub fn download_from_playlist(url: &str, filename: &str, verbose: bool) -> Resul
         .expect("Could not read the playlist source");
 
     if verbose {
-        println!("{}", "Parsing ...");
+        println!("Parsing ...");
     }
 
     // Parse the playlist:
-    let playlist = m3u8_rs::parse_media_playlist(&playlist_text.as_bytes())
+    let playlist = m3u8_rs::parse_media_playlist(playlist_text.as_bytes())
         .finish()
         .unwrap();
 
-    // Grab and concatenate the segments from the playlist:
-    let file = Path::new(&filename);
-    let mut dest = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&file)?;
+    // Find out whether we have ads in there:
+    let mut groups = group_segments(&url, &playlist.1.segments);
+    let ad_cnt = groups.iter().filter(|g| g.looks_like_ad).count();
+    if ad_cnt > 0 {
+        if skip_ads {
+            println!("Skipping {} segment group(s) that look like ads.", ad_cnt);
+            groups.retain(|g| !g.looks_like_ad);
+        } else {
+            println!(
+                "This stream contains {} segment group(s) that look like ads. Use --skip-ads to drop them.",
+                ad_cnt
+            );
+        }
+    }
 
-    // Display a progress bar:
-    let total_cnt = playlist.1.segments.len() as u64;
-    let pb = ProgressBar::new(total_cnt);
-    pb.set_style(
-        ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}] [{bar:40.green/blue}] {percent}%",
-        )
-        .unwrap()
-        .progress_chars("#>-"),
-    );
+    // fMP4 streams have an initialization section (EXT-X-MAP) that
+    // needs to come first in every file:
+    let mut map_url: Option<Url> = None;
 
-    for segment in &playlist.1.segments {
-        // .m3u8 playlists are usually relative.
-        // Take the original path (from the playlist) and replace
-        // the playlist itself by the video (e.g):
-        //   playlist URL:  https://foo.bar/play/file.m3u8
-        //   playlist item: file1.ts
-        //   result:        https://foo.bar/play/file1.ts
-        url.path_segments_mut().unwrap().pop().push(&segment.uri);
-
-        let mut request = agent
-            .get(url.as_str())
-            .header("Referer", url.as_str())
-            .call()?;
-        let mut source = request.body_mut().as_reader();
+    let mut first_time = 0.0;
+    if let Some(section) = section {
+        let (time, map_uri) = select_section(&mut groups, section);
+        if groups.is_empty() {
+            return Err(anyhow!("The stream does not cover the requested section."));
+        }
+        first_time = time;
+        map_url = map_uri.map(|uri| url.join(uri)).transpose()?;
+    }
+    if verbose && groups.len() > 1 {
+        println!("Downloading {} separate segment groups.", groups.len());
+    }
+
+    // Some playlists do not have proper durations. Weigh every segment
+    // equally then.
+    let has_durations = groups
+        .iter()
+        .flat_map(|g| &g.segments)
+        .all(|s| s.duration > 0.0);
+    let segment_duration = |segment: &MediaSegment| {
+        if has_durations {
+            segment.duration as f64
+        } else {
+            1.0
+        }
+    };
+    let total_duration: f64 = groups
+        .iter()
+        .flat_map(|g| &g.segments)
+        .map(|s| segment_duration(s))
+        .sum();
+    let mut done_duration = 0.0;
+
+    // Display a progress bar. Its length is unknown until the first
+    // segment arrives:
+    let pb = progress_bar(0);
+
+    let mut files = vec![];
+    for (num, group) in groups.iter().enumerate() {
+        // Grab and concatenate the segments from the group:
+        let file = if groups.len() == 1 {
+            PathBuf::from(filename)
+        } else {
+            part_file_name(filename, num + 1)
+        };
+        let mut dest = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file)?;
+        let mut written_map_url = None;
+
+        for segment in &group.segments {
+            if let Some(map) = &segment.map {
+                map_url = Some(url.join(&map.uri)?);
+            }
+            if let Some(map_url) = map_url
+                .as_ref()
+                .filter(|m| written_map_url.as_ref() != Some(*m))
+            {
+                let mut request = with_headers(agent.get(map_url.as_str()), headers).call()?;
+                let _ = copy(&mut request.body_mut().as_reader(), &mut dest)?;
+                written_map_url = Some(map_url.clone());
+            }
+
+            // .m3u8 playlists are usually relative.
+            // Take the original path (from the playlist) and replace
+            // the playlist itself by the video (e.g):
+            //   playlist URL:  https://foo.bar/play/file.m3u8
+            //   playlist item: file1.ts
+            //   result:        https://foo.bar/play/file1.ts
+            let segment_url = url.join(&segment.uri)?;
+
+            let mut request = with_headers(agent.get(segment_url.as_str()), headers).call()?;
+
+            // If the server tells us the segment size, we can update the
+            // estimate before the segment is downloaded:
+            let this_duration = segment_duration(segment);
+            if let Some(segment_size) = request.body().content_length() {
+                pb.set_length(estimate_playlist_size(
+                    pb.position() + segment_size,
+                    done_duration + this_duration,
+                    total_duration,
+                ));
+            }
+
+            let mut source = DownloadProgress {
+                progress_bar: &pb,
+                inner: request.body_mut().as_reader(),
+            };
 
-        // Note: As we opened the file for appending only,
-        // file concatenation happens automatically.
-        let _ = copy(&mut source, &mut dest)?;
+            // Note: As we opened the file for appending only,
+            // file concatenation happens automatically.
+            let _ = copy(&mut source, &mut dest)?;
 
-        // Update the progress bar:
-        pb.inc(1);
+            // Update the estimate with what we actually got:
+            done_duration += this_duration;
+            pb.set_length(
+                estimate_playlist_size(pb.position(), done_duration, total_duration)
+                    .max(pb.position()),
+            );
+        }
+
+        files.push(file);
     }
 
     pb.finish_and_clear();
 
-    Ok(())
+    Ok((files, first_time))
 }
 
-pub fn download(url: &str, filename: &str) -> Result<()> {
+pub fn download(
+    agent: &YaydlAgent,
+    url: &str,
+    filename: &str,
+    headers: &[(String, String)],
+) -> Result<()> {
     let url = Url::parse(url)?;
-    let agent = YaydlAgent::init(url.clone());
 
-    let mut resp = agent
-        .get(url.as_str())
-        .header("Referer", url.as_str())
-        .call()?;
+    let mut resp = with_headers(agent.get(url.as_str()), headers).call()?;
 
-    // Find the video size:
-    let total_size = resp
-        .body()
-        .content_length()
-        .expect("Failed to read the segment size. Aborting.");
+    // Find the video size. It is unknown for replays and for some
+    // servers, the download works without it:
+    let total_size = resp.body().content_length().unwrap_or(0);
 
     // Display a progress bar:
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(
-        ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}] [{bar:40.green/blue}] {percent}%",
-        )
-        .unwrap()
-        .progress_chars("#>-"),
-    );
+    let pb = progress_bar(total_size);
 
     let file = Path::new(filename);
 
@@ -145,9 +496,7 @@ pub fn download(url: &str, filename: &str) -> Result<()> {
         // Continue the file:
         let size = file.metadata()?.len() - 1;
         // Override the range:
-        resp = agent
-            .get(url.as_str())
-            .header("Referer", url.as_str())
+        resp = with_headers(agent.get(url.as_str()), headers)
             .header("Range", &format!("bytes={}-", size))
             .call()?;
         pb.inc(size);
@@ -161,7 +510,7 @@ pub fn download(url: &str, filename: &str) -> Result<()> {
     let mut dest = fs::OpenOptions::new()
         .create(true)
         .append(true)
-        .open(&file)?;
+        .open(file)?;
 
     let _ = copy(&mut source, &mut dest)?;
 
@@ -169,3 +518,291 @@ pub fn download(url: &str, filename: &str) -> Result<()> {
 
     Ok(())
 }
+
+// Downloads the thumbnail at <url> next to <videofile> and returns
+// its file name. The extension is taken from the image type.
+pub fn download_thumbnail(agent: &YaydlAgent, url: &str, videofile: &Path) -> Result<PathBuf> {
+    let mut resp = agent.get(url).call()?;
+
+    let ext = match resp.body().mime_type() {
+        Some("image/png") => "png",
+        Some("image/webp") => "webp",
+        Some("image/avif") => "avif",
+        _ => "jpg",
+    };
+    let file = videofile.with_extension(ext);
+
+    let image = resp.body_mut().read_to_vec()?;
+    fs::write(&file, image)?;
+
+    Ok(file)
+}
+
+// Downloads one stream of a pair to <filename>.
+fn download_stream(
+    agent: &YaydlAgent,
+    stream: &Stream,
+    filename: &str,
+    headers: &[(String, String)],
+    verbose: bool,
+) -> Result<PathBuf> {
+    if !stream.is_playlist {
+        download(agent, &stream.url, filename, headers)?;
+        return Ok(PathBuf::from(filename));
+    }
+
+    let (mut files, _) =
+        download_from_playlist(agent, &stream.url, filename, headers, false, None, verbose)?;
+    if files.len() > 1 {
+        return Err(anyhow!(
+            "{} consists of separate parts, it cannot be merged.",
+            stream.url
+        ));
+    }
+    Ok(files.remove(0))
+}
+
+// Downloads the video and the audio stream of <pair> at the same time.
+// Returns the video file and the audio file, both named after <filename>.
+pub fn download_stream_pair(
+    agent: &YaydlAgent,
+    pair: &StreamPair,
+    filename: &str,
+    headers: &[(String, String)],
+    verbose: bool,
+) -> Result<Vec<PathBuf>> {
+    let file = Path::new(filename);
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let videofile = file.with_file_name(format!("{}.video.{}", stem, pair.video.ext));
+    let audiofile = file.with_file_name(format!("{}.audio.{}", stem, pair.audio.ext));
+    let videofile = videofile.to_string_lossy();
+    let audiofile = audiofile.to_string_lossy();
+
+    thread::scope(|scope| {
+        let video =
+            scope.spawn(|| download_stream(agent, &pair.video, &videofile, headers, verbose));
+        let audio =
+            scope.spawn(|| download_stream(agent, &pair.audio, &audiofile, headers, verbose));
+
+        let videofile = video
+            .join()
+            .map_err(|_| anyhow!("The video download has crashed."))??;
+        let audiofile = audio
+            .join()
+            .map_err(|_| anyhow!("The audio download has crashed."))??;
+        Ok(vec![videofile, audiofile])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(playlist: &str) -> Vec<MediaSegment> {
+        m3u8_rs::parse_media_playlist(playlist.as_bytes())
+            .finish()
+            .unwrap()
+            .1
+            .segments
+    }
+
+    fn uris<'a>(group: &SegmentGroup<'a>) -> Vec<&'a str> {
+        group.segments.iter().map(|s| s.uri.as_str()).collect()
+    }
+
+    const PLAYLIST_WITH_ADS: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXTINF:10,
+video/1.ts
+#EXTINF:10,
+video/2.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:5,
+https://ads.example.net/spot/1.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:10,
+video/3.ts
+#EXTINF:10,
+video/4.ts
+#EXTINF:10,
+video/5.ts
+#EXT-X-DISCONTINUITY
+#EXT-X-CUE-OUT:6
+#EXTINF:6,
+video/break.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:10,
+video/6.ts
+#EXT-X-ENDLIST
+";
+
+    #[test]
+    fn refers_to_the_page_unless_the_handler_does() {
+        let page = "https://site.test/watch/1";
+        assert_eq!(
+            with_page_referer(vec![], page),
+            [("Referer".to_string(), page.to_string())]
+        );
+
+        let headers = vec![
+            ("referer".to_string(), "https://site.test/".to_string()),
+            ("X-Site".to_string(), "1".to_string()),
+        ];
+        assert_eq!(with_page_referer(headers.clone(), page), headers);
+    }
+
+    #[test]
+    fn estimates_the_playlist_size() {
+        // 2 MB for the first 10 of 60 seconds:
+        assert_eq!(estimate_playlist_size(2_000_000, 10.0, 60.0), 12_000_000);
+        assert_eq!(estimate_playlist_size(2_000_000, 60.0, 60.0), 2_000_000);
+        // Nothing to extrapolate from yet:
+        assert_eq!(estimate_playlist_size(0, 0.0, 60.0), 0);
+        assert_eq!(estimate_playlist_size(500, 0.0, 60.0), 500);
+    }
+
+    #[test]
+    fn groups_segments_at_discontinuities() {
+        let playlist_url = Url::parse("https://cdn.example.com/stream/index.m3u8").unwrap();
+        let segments = segments(PLAYLIST_WITH_ADS);
+        let groups = group_segments(&playlist_url, &segments);
+
+        let groups: Vec<_> = groups.iter().map(|g| (uris(g), g.looks_like_ad)).collect();
+        assert_eq!(
+            groups,
+            [
+                (vec!["video/1.ts", "video/2.ts"], false),
+                // From another server:
+                (vec!["https://ads.example.net/spot/1.ts"], true),
+                (vec!["video/3.ts", "video/4.ts", "video/5.ts"], false),
+                // With a cue:
+                (vec!["video/break.ts"], true),
+                (vec!["video/6.ts"], false),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_ads_in_date_ranges() {
+        let segments = segments(
+            r#"#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-DATERANGE:ID="1",CLASS="com.example.ad-break",START-DATE="2024-01-01T00:00:00Z"
+#EXTINF:10,
+1.ts
+#EXT-X-DATERANGE:ID="2",START-DATE="2024-01-01T00:00:10Z",SCTE35-OUT=0xFC30
+#EXTINF:10,
+2.ts
+#EXT-X-DATERANGE:ID="3",CLASS="com.example.broadcast",START-DATE="2024-01-01T00:00:20Z"
+#EXTINF:10,
+3.ts
+#EXT-X-DATERANGE:ID="4",CLASS="upload.header",START-DATE="2024-01-01T00:00:30Z"
+#EXTINF:10,
+4.ts
+#EXT-X-DATERANGE:ID="5",CLASS="com.example.chapter",START-DATE="2024-01-01T00:00:40Z",X-SCTE35-NOTE="none"
+#EXTINF:10,
+5.ts
+#EXT-X-ENDLIST
+"#,
+        );
+        let ads: Vec<_> = segments.iter().map(has_ad_markers).collect();
+        // "broadcast", "upload" and "header" only contain "ad":
+        assert_eq!(ads, [true, true, false, false, false]);
+    }
+
+    #[test]
+    fn keeps_playlists_without_discontinuities_together() {
+        let playlist_url = Url::parse("https://cdn.example.com/index.m3u8").unwrap();
+        let segments = segments(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4,\na.ts\n#EXTINF:4,\nb.ts\n#EXT-X-ENDLIST\n",
+        );
+        let groups = group_segments(&playlist_url, &segments);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(uris(&groups[0]), ["a.ts", "b.ts"]);
+        assert!(!groups[0].looks_like_ad);
+
+        assert!(group_segments(&playlist_url, &[]).is_empty());
+    }
+
+    #[test]
+    fn parses_sections() {
+        let section = parse_section("*10:00-15:30").unwrap();
+        assert_eq!((section.start, section.end), (600.0, Some(930.0)));
+        assert_eq!(section.duration(), Some(330.0));
+
+        let section = parse_section("1:02:03.5-inf").unwrap();
+        assert_eq!((section.start, section.end), (3723.5, None));
+        assert_eq!(section.duration(), None);
+        assert_eq!(parse_section("*90-").unwrap().end, None);
+
+        for section in [
+            "10:00",
+            "*15:30-10:00",
+            "*5-5",
+            "*a-b",
+            "*1:2:3:4-inf",
+            "*-1-5",
+        ] {
+            assert!(parse_section(section).is_err(), "{}", section);
+        }
+    }
+
+    #[test]
+    fn selects_the_segments_of_a_section() {
+        let playlist_url = Url::parse("https://cdn.example.com/stream/index.m3u8").unwrap();
+        let segments = segments(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-MAP:URI=\"init1.mp4\"
+#EXTINF:10,
+1.m4s
+#EXTINF:10,
+2.m4s
+#EXT-X-DISCONTINUITY
+#EXT-X-MAP:URI=\"init2.mp4\"
+#EXTINF:10,
+3.m4s
+#EXTINF:10,
+4.m4s
+#EXTINF:10,
+5.m4s
+#EXT-X-ENDLIST
+",
+        );
+
+        // 25 s to 32 s: the second half of the 3rd segment to the 4th one.
+        let mut groups = group_segments(&playlist_url, &segments);
+        let section = Section {
+            start: 25.0,
+            end: Some(32.0),
+        };
+        assert_eq!(
+            select_section(&mut groups, &section),
+            (20.0, Some("init2.mp4"))
+        );
+        assert_eq!(groups.len(), 1);
+        assert_eq!(uris(&groups[0]), ["3.m4s", "4.m4s"]);
+
+        // Until the end, over a discontinuity:
+        let mut groups = group_segments(&playlist_url, &segments);
+        let section = Section {
+            start: 15.0,
+            end: None,
+        };
+        assert_eq!(
+            select_section(&mut groups, &section),
+            (10.0, Some("init1.mp4"))
+        );
+        let groups: Vec<_> = groups.iter().map(uris).collect();
+        assert_eq!(groups, [vec!["2.m4s"], vec!["3.m4s", "4.m4s", "5.m4s"]]);
+
+        // After the end of the video:
+        let mut groups = group_segments(&playlist_url, &segments);
+        let section = Section {
+            start: 60.0,
+            end: None,
+        };
+        assert_eq!(select_section(&mut groups, &section), (0.0, None));
+        assert!(groups.is_empty());
+    }
+}
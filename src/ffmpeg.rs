@@ -16,35 +16,997 @@
 // Yet Another Youtube Down Loader
 // - ffmpeg.rs file -
 
-use std::path::Path;
-use std::process::Command;
+use anyhow::{anyhow, Result};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use crate::agent::YaydlAgent;
+use crate::definitions::{StreamPair, VideoMetadata};
+use crate::download::{self, Section};
+use crate::recording;
 
 // It makes very little sense to link ffmpeg statically with yaydl.
 // Just use the system's one (or inform the user if there isn't one).
 
-pub fn to_audio(inputfile: &Path, outputfile: &Path) {
-    Command::new("ffmpeg")
-        .arg("-i")
-        .arg(inputfile)
-        .arg("-vn") // Skip the video streams.
-        .arg("-loglevel")
-        .arg("quiet") // Shut the fuck up.
-        .arg(outputfile)
+// The oldest ffmpeg version yaydl is known to work with.
+const MIN_VERSION: (u32, u32) = (4, 0);
+
+// Where ffmpeg and ffprobe are.
+struct Tools {
+    ffmpeg: PathBuf,
+    ffprobe: Option<PathBuf>,
+}
+
+static LOCATION: OnceLock<PathBuf> = OnceLock::new();
+static TOOLS: OnceLock<Result<Tools, String>> = OnceLock::new();
+
+// Sets --ffmpeg-location (a directory or the ffmpeg binary itself).
+// Fails if a location is set (here or with $YAYDL_FFMPEG), but has no
+// ffmpeg: silently using another one would be a surprise.
+pub fn set_location(location: Option<PathBuf>) -> Result<()> {
+    if let Some(location) = location {
+        let _ = LOCATION.set(location);
+    }
+    match find_tools() {
+        Err(e) if chosen_location().is_some() => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// --ffmpeg-location or $YAYDL_FFMPEG.
+fn chosen_location() -> Option<PathBuf> {
+    LOCATION
+        .get()
+        .cloned()
+        .or_else(|| env::var_os("YAYDL_FFMPEG").map(PathBuf::from))
+}
+
+// Finds <name> in <location>, which can be the directory or the binary.
+// For the binary, its sibling <name> is returned (ffmpeg -> ffprobe).
+fn find_in(location: &Path, name: &str) -> Option<PathBuf> {
+    let name = format!("{}{}", name, env::consts::EXE_SUFFIX);
+    let candidate = if location.is_dir() {
+        location.join(&name)
+    } else {
+        location.with_file_name(&name)
+    };
+    candidate.is_file().then_some(candidate)
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?).find_map(|dir| find_in(&dir, name))
+}
+
+// Looks for the tools in this order:
+// 1. --ffmpeg-location
+// 2. $YAYDL_FFMPEG
+// 3. $PATH
+fn find_tools() -> Result<&'static Tools> {
+    TOOLS
+        .get_or_init(|| tools_at(chosen_location().as_deref()))
+        .as_ref()
+        .map_err(|e| anyhow!("{}", e))
+}
+
+// Finds the tools in <location>, or in $PATH without one.
+fn tools_at(location: Option<&Path>) -> Result<Tools, String> {
+    match location {
+        Some(location) => {
+            let ffmpeg = if location.is_file() {
+                Some(location.to_path_buf())
+            } else {
+                find_in(location, "ffmpeg")
+            };
+            Ok(Tools {
+                ffmpeg: ffmpeg.ok_or_else(|| {
+                    format!(
+                        "There is no ffmpeg in {} (--ffmpeg-location or YAYDL_FFMPEG).",
+                        location.display()
+                    )
+                })?,
+                ffprobe: find_in(location, "ffprobe"),
+            })
+        }
+        None => Ok(Tools {
+            ffmpeg: find_in_path("ffmpeg").ok_or_else(|| {
+                "Please install ffmpeg (or tell yaydl where it is with --ffmpeg-location) to convert the file.".to_string()
+            })?,
+            ffprobe: find_in_path("ffprobe"),
+        }),
+    }
+}
+
+fn tools() -> Option<&'static Tools> {
+    find_tools().ok()
+}
+
+// How the <format> audio files are made: their file extension, the
+// ffmpeg encoder and the codec (as ffprobe names it). Other formats are
+// left to ffmpeg.
+struct AudioCodec {
+    ext: &'static str,
+    encoder: &'static str,
+    codec: &'static str,
+}
+
+fn audio_codec(format: &str) -> Option<AudioCodec> {
+    let (ext, encoder, codec) = match format {
+        "mp3" => ("mp3", "libmp3lame", "mp3"),
+        "aac" => ("aac", "aac", "aac"),
+        "m4a" => ("m4a", "aac", "aac"),
+        "opus" => ("opus", "libopus", "opus"),
+        "ogg" | "vorbis" => ("ogg", "libvorbis", "vorbis"),
+        "flac" => ("flac", "flac", "flac"),
+        "wav" => ("wav", "pcm_s16le", "pcm_s16le"),
+        _ => return None,
+    };
+    Some(AudioCodec {
+        ext,
+        encoder,
+        codec,
+    })
+}
+
+// Returns the file extension for --audio-format.
+pub fn audio_extension(format: &str) -> String {
+    audio_codec(format)
+        .map_or(format, |codec| codec.ext)
+        .to_string()
+}
+
+// --audio-quality: a VBR level from 0 (best) to 10 (worst) or a bitrate.
+#[derive(Clone, Copy)]
+pub enum AudioQuality {
+    Vbr(u8),
+    Bitrate(u32),
+}
+
+// Parses "0" to "10" or a bitrate in kbit/s ("128K").
+pub fn parse_audio_quality(quality: &str) -> Result<AudioQuality, String> {
+    let invalid = || {
+        format!(
+            "\"{}\" is neither a VBR level (0-10) nor a bitrate (e.g. 128K).",
+            quality
+        )
+    };
+    match quality.strip_suffix(['K', 'k']) {
+        Some(bitrate) => match bitrate.parse() {
+            Ok(bitrate) if bitrate > 0 => Ok(AudioQuality::Bitrate(bitrate)),
+            _ => Err(invalid()),
+        },
+        None => match quality.parse() {
+            Ok(level) if level <= 10 => Ok(AudioQuality::Vbr(level)),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+// The options for --only-audio.
+pub struct AudioOptions {
+    pub format: String,
+    pub quality: Option<AudioQuality>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+}
+
+impl AudioOptions {
+    // Whether the audio can be kept as it is, if it is in the right
+    // codec already.
+    fn can_copy(&self) -> bool {
+        self.quality.is_none() && self.sample_rate.is_none() && self.channels.is_none()
+    }
+}
+
+// AAC and Opus have no usable VBR scale in ffmpeg, so the VBR levels
+// are mapped to bitrates (in kbit/s) for them.
+const VBR_BITRATES: [u32; 11] = [256, 224, 192, 160, 144, 128, 112, 96, 80, 64, 48];
+
+fn quality_args(encoder: &str, quality: AudioQuality) -> Vec<String> {
+    let args = match (encoder, quality) {
+        // Lossless, there is nothing to choose.
+        ("flac" | "pcm_s16le", _) => return vec![],
+        (_, AudioQuality::Bitrate(bitrate)) => ("-b:a", format!("{}k", bitrate)),
+        ("libmp3lame", AudioQuality::Vbr(level)) => ("-q:a", level.min(9).to_string()),
+        ("libvorbis", AudioQuality::Vbr(level)) => ("-q:a", (10 - level).to_string()),
+        (_, AudioQuality::Vbr(level)) => ("-b:a", format!("{}k", VBR_BITRATES[level as usize])),
+    };
+    vec![args.0.to_string(), args.1]
+}
+
+// Parses "ffmpeg version 6.1.1 ..." (or "n6.1.1"). Development builds
+// have no version number at all.
+fn parse_version(version_output: &str) -> Option<(u32, u32)> {
+    let version = version_output
+        .lines()
+        .next()?
+        .strip_prefix("ffmpeg version ")?
+        .trim_start_matches('n');
+    let mut numbers = version.split(|c: char| !c.is_ascii_digit());
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+    Some((major, minor))
+}
+
+// Returns true if ffmpeg could be found.
+pub fn is_available() -> bool {
+    tools().is_some()
+}
+
+// Makes sure that a usable ffmpeg is there before anything is
+// downloaded. <audio> are the options for --only-audio; their encoder is
+// only needed if the audio is going to be re-encoded for sure.
+pub fn check(audio: Option<&AudioOptions>, verbose: bool) -> Result<()> {
+    let tools = find_tools()?;
+
+    let output = Command::new(&tools.ffmpeg)
+        .arg("-version")
+        .output()
+        .map_err(|e| anyhow!("Could not run {}: {}", tools.ffmpeg.display(), e))?;
+    let version_output = String::from_utf8_lossy(&output.stdout);
+    match parse_version(&version_output) {
+        Some(version) if version < MIN_VERSION => {
+            return Err(anyhow!(
+                "Your ffmpeg is too old ({}.{}), please install ffmpeg {}.{} or newer.",
+                version.0,
+                version.1,
+                MIN_VERSION.0,
+                MIN_VERSION.1
+            ));
+        }
+        _ => {}
+    }
+
+    if verbose {
+        println!(
+            "Using {} ({}).",
+            version_output.lines().next().unwrap_or("ffmpeg"),
+            tools.ffmpeg.display()
+        );
+        match &tools.ffprobe {
+            Some(ffprobe) => println!("Using {}.", ffprobe.display()),
+            None => println!("ffprobe could not be found."),
+        }
+    }
+
+    // Audio in the right codec already is copied, the encoder is
+    // checked in to_audio() then.
+    match audio {
+        Some(audio) if !audio.can_copy() => check_encoder(&audio.format),
+        _ => Ok(()),
+    }
+}
+
+// Makes sure that ffmpeg can encode <format> audio files.
+fn check_encoder(format: &str) -> Result<()> {
+    let (Some(tools), Some(AudioCodec { encoder, .. })) = (tools(), audio_codec(format)) else {
+        return Ok(());
+    };
+
+    let output = Command::new(&tools.ffmpeg)
+        .arg("-hide_banner")
+        .arg("-encoders")
         .output()
-        .expect("Please install ffmpeg to convert the file into audio.");
+        .map_err(|e| anyhow!("Could not run {}: {}", tools.ffmpeg.display(), e))?;
+    let has_encoder = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(encoder));
+    if !has_encoder {
+        return Err(anyhow!(
+            "Your ffmpeg cannot encode {} files (the \"{}\" encoder is missing).",
+            format,
+            encoder
+        ));
+    }
+    Ok(())
 }
 
-pub fn ts_to_mp4(inputfile: &Path, outputfile: &Path) {
-    Command::new("ffmpeg")
+// Returns the codec of the first audio stream in <file> (e.g. "aac"),
+// if ffprobe is there and knows.
+fn probe_audio_codec(file: &Path) -> Option<String> {
+    let output = Command::new(tools()?.ffprobe.as_ref()?)
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("a:0")
+        .arg("-show_entries")
+        .arg("stream=codec_name")
+        .arg("-of")
+        .arg("default=noprint_wrappers=1:nokey=1")
+        .arg(file)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let codec = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!codec.is_empty()).then_some(codec)
+}
+
+// Adds the input file(s) to <cmd>. Multiple inputs are glued together
+// with ffmpeg's concat demuxer, which keeps their timestamps apart.
+// Returns the concat list file, if any, so it can be removed later.
+fn add_inputs(cmd: &mut Command, inputfiles: &[PathBuf]) -> Result<Option<PathBuf>> {
+    if inputfiles.is_empty() {
+        // The inputs are URLs in the arguments.
+        return Ok(None);
+    }
+    if inputfiles.len() == 1 {
+        cmd.arg("-i").arg(&inputfiles[0]);
+        return Ok(None);
+    }
+
+    let mut listfile = inputfiles[0].clone();
+    listfile.set_extension("concat.txt");
+    let list: String = inputfiles
+        .iter()
+        .map(|f| {
+            let abs = fs::canonicalize(f).unwrap_or_else(|_| f.to_path_buf());
+            format!("file '{}'\n", abs.to_string_lossy().replace('\'', r"'\''"))
+        })
+        .collect();
+    fs::write(&listfile, list).map_err(|e| anyhow!("Could not write the list of parts: {}", e))?;
+
+    cmd.arg("-f")
+        .arg("concat")
+        .arg("-safe")
+        .arg("0")
         .arg("-i")
-        .arg(inputfile)
-        .arg("-acodec")
-        .arg("copy")
-        .arg("-vcodec")
-        .arg("copy")
-        .arg("-loglevel")
-        .arg("quiet") // Shut the fuck up.
-        .arg(outputfile)
+        .arg(&listfile);
+    Ok(Some(listfile))
+}
+
+// Returns the duration of <file> as ffprobe sees it.
+fn probe_duration(file: &Path) -> Option<Duration> {
+    let output = Command::new(tools()?.ffprobe.as_ref()?)
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("format=duration")
+        .arg("-of")
+        .arg("default=noprint_wrappers=1:nokey=1")
+        .arg(file)
         .output()
-        .expect("Please install ffmpeg to convert the file into MP4.");
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let seconds: f64 = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+// How much media ffmpeg is going to write: all of <inputfiles>, unless
+// <args> skip a part with "-ss" or limit it with "-t".
+fn output_duration(inputfiles: &[PathBuf], args: &[String]) -> Option<Duration> {
+    if inputfiles.is_empty() {
+        // Remote inputs cannot be probed, only "-t" tells.
+        return trimmed_duration(None, args);
+    }
+
+    let total = inputfiles
+        .iter()
+        .map(|file| probe_duration(file))
+        .sum::<Option<Duration>>();
+    trimmed_duration(total, args)
+}
+
+// What is left of <total> after the "-ss" and "-t" in <args>.
+fn trimmed_duration(total: Option<Duration>, args: &[String]) -> Option<Duration> {
+    let seconds_after = |option: &str| {
+        args.iter()
+            .position(|arg| arg == option)
+            .and_then(|i| args.get(i + 1)?.parse::<f64>().ok())
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+    };
+
+    let total = match (total, seconds_after("-ss")) {
+        (Some(total), Some(offset)) => Some(total.saturating_sub(offset)),
+        (total, _) => total,
+    };
+    match (total, seconds_after("-t")) {
+        (Some(total), Some(limit)) => Some(total.min(limit)),
+        (total, limit) => total.or(limit),
+    }
+}
+
+// Drives a progress bar with what "-progress pipe:1" tells about <child>.
+// Returns its exit status and everything it wrote to stderr.
+fn show_progress(mut child: Child, duration: Option<Duration>) -> io::Result<(ExitStatus, String)> {
+    // Read stderr in the background, so ffmpeg never blocks on it:
+    let mut stderr = child.stderr.take();
+    let stderr_reader = thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(stderr) = &mut stderr {
+            let _ = stderr.read_to_string(&mut output);
+        }
+        output
+    });
+
+    let pb = download::conversion_progress_bar(duration);
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            // out_time_us is "N/A" until the first frame is written.
+            if let Some(Ok(us)) = line.strip_prefix("out_time_us=").map(str::parse::<i64>) {
+                let done = Duration::from_micros(us.max(0) as u64);
+                pb.set_message(format!(
+                    "{}{}",
+                    format_time(done),
+                    duration.map_or(String::new(), |d| format!("/{}", format_time(d)))
+                ));
+                pb.set_position(done.as_millis() as u64);
+            }
+        }
+    }
+    pb.finish_and_clear();
+
+    let status = child.wait()?;
+    Ok((status, stderr_reader.join().unwrap_or_default()))
+}
+
+// Formats <time> as H:MM:SS.
+fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// Runs ffmpeg with <inputfiles>, <args> and <outputfile>. Succeeds only
+// if ffmpeg does and <outputfile> is not empty; otherwise, the error
+// contains what ffmpeg had to say.
+fn run(inputfiles: &[PathBuf], args: &[String], outputfile: &Path) -> Result<()> {
+    // ffmpeg cannot overwrite its input (e.g. "-o video.mp4" for a
+    // stream), so convert into a temporary file first:
+    if inputfiles.iter().any(|f| f == outputfile) {
+        let mut tempfile = outputfile.to_path_buf();
+        let ext = outputfile.extension().unwrap_or_default().to_string_lossy();
+        tempfile.set_extension(format!("converting.{}", ext));
+        run(inputfiles, args, &tempfile)?;
+        fs::rename(&tempfile, outputfile)?;
+        return Ok(());
+    }
+
+    let ffmpeg = find_tools()?;
+    let mut cmd = Command::new(&ffmpeg.ffmpeg);
+    cmd.arg("-nostdin").arg("-loglevel").arg("error"); // Only talk if something goes wrong.
+    cmd.arg("-progress").arg("pipe:1").arg("-nostats");
+    let listfile = add_inputs(&mut cmd, inputfiles)?;
+    let output = cmd
+        .args(args)
+        .arg(outputfile)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|child| show_progress(child, output_duration(inputfiles, args)));
+
+    if let Some(listfile) = listfile {
+        let _ = fs::remove_file(listfile);
+    }
+
+    let (status, stderr) = output.map_err(|e| anyhow!("Could not run ffmpeg: {}", e))?;
+
+    if !status.success() {
+        return Err(anyhow!("ffmpeg failed ({}):\n{}", status, stderr.trim()));
+    }
+
+    let size = fs::metadata(outputfile).map(|m| m.len()).unwrap_or(0);
+    if size == 0 {
+        return Err(anyhow!(
+            "ffmpeg did not write {}.",
+            outputfile.to_string_lossy()
+        ));
+    }
+
+    Ok(())
+}
+
+// The formats --embed-metadata can write tags into.
+pub fn can_embed_metadata(ext: &str) -> bool {
+    matches!(
+        ext,
+        "mp4" | "mkv" | "webm" | "mp3" | "m4a" | "opus" | "flac"
+    )
+}
+
+// Returns the tags for --embed-metadata. The source URL goes into
+// "comment" (shown by most players) and "purl" (Matroska).
+pub fn metadata_tags(
+    title: &str,
+    metadata: &VideoMetadata,
+    source_url: &str,
+) -> Vec<(String, String)> {
+    let mut tags = vec![("title".to_string(), title.to_string())];
+    if let Some(uploader) = &metadata.uploader {
+        tags.push(("artist".to_string(), uploader.to_string()));
+    }
+    if let Some(upload_date) = &metadata.upload_date {
+        tags.push(("date".to_string(), upload_date.to_string()));
+    }
+    if let Some(description) = &metadata.description {
+        tags.push(("description".to_string(), description.to_string()));
+        tags.push(("synopsis".to_string(), description.to_string()));
+    }
+    tags.push(("comment".to_string(), source_url.to_string()));
+    tags.push(("purl".to_string(), source_url.to_string()));
+    tags
+}
+
+// Returns <args>, followed by "-metadata key=value" for all <tags>.
+fn with_tags(args: &[&str], tags: &[(String, String)]) -> Vec<String> {
+    let mut all_args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    for (key, value) in tags {
+        all_args.push("-metadata".to_string());
+        all_args.push(format!("{}={}", key, value));
+    }
+    all_args
+}
+
+pub fn to_audio(
+    inputfiles: &[PathBuf],
+    outputfile: &Path,
+    options: &AudioOptions,
+    tags: &[(String, String)],
+) -> Result<()> {
+    // Skip the video streams.
+    let mut args = vec!["-vn".to_string()];
+    if let Some(AudioCodec { encoder, codec, .. }) = audio_codec(&options.format) {
+        // If the audio is in the right codec already and nothing needs
+        // to be changed, copy it as it is. That is lossless and fast.
+        if options.can_copy() && probe_audio_codec(&inputfiles[0]).as_deref() == Some(codec) {
+            args.extend(["-c:a".to_string(), "copy".to_string()]);
+        } else {
+            check_encoder(&options.format)?;
+            args.extend(["-c:a".to_string(), encoder.to_string()]);
+            if let Some(quality) = options.quality {
+                args.extend(quality_args(encoder, quality));
+            }
+        }
+    }
+    if let Some(sample_rate) = options.sample_rate {
+        args.extend(["-ar".to_string(), sample_rate.to_string()]);
+    }
+    if let Some(channels) = options.channels {
+        args.extend(["-ac".to_string(), channels.to_string()]);
+    }
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    run(inputfiles, &with_tags(&args, tags), outputfile)
+}
+
+pub fn ts_to_mp4(
+    inputfiles: &[PathBuf],
+    outputfile: &Path,
+    tags: &[(String, String)],
+) -> Result<()> {
+    let args = with_tags(&["-acodec", "copy", "-vcodec", "copy"], tags);
+    run(inputfiles, &args, outputfile)
+}
+
+// Writes <tags> into <file> without converting it.
+pub fn embed_metadata(file: &Path, tags: &[(String, String)]) -> Result<()> {
+    let args = with_tags(&["-map", "0", "-c", "copy"], tags);
+    run(&[file.to_path_buf()], &args, file)
+}
+
+// The formats --embed-thumbnail can put cover art into.
+pub fn can_embed_thumbnail(ext: &str) -> bool {
+    matches!(ext, "mp3" | "m4a" | "mp4" | "mkv" | "flac")
+}
+
+// Attaches <thumbnail> to <file> as its cover art.
+pub fn embed_thumbnail(file: &Path, thumbnail: &Path) -> Result<()> {
+    // Covers need to be JPEG or PNG images:
+    let thumbnail_ext = thumbnail.extension().unwrap_or_default().to_string_lossy();
+    if !matches!(thumbnail_ext.as_ref(), "jpg" | "png") {
+        let mut jpg = thumbnail.to_path_buf();
+        jpg.set_extension(format!("{}.jpg", thumbnail_ext));
+        run(&[thumbnail.to_path_buf()], &[], &jpg)?;
+        let result = embed_thumbnail(file, &jpg);
+        let _ = fs::remove_file(jpg);
+        return result;
+    }
+
+    let (mimetype, filename) = match thumbnail_ext.as_ref() {
+        "png" => ("mimetype=image/png", "filename=cover.png"),
+        _ => ("mimetype=image/jpeg", "filename=cover.jpg"),
+    };
+
+    let thumbnail = thumbnail.to_string_lossy();
+    let ext = file.extension().unwrap_or_default().to_string_lossy();
+    let args: Vec<&str> = match ext.as_ref() {
+        // Matroska has real attachments:
+        "mkv" => vec![
+            "-map",
+            "0",
+            "-c",
+            "copy",
+            "-attach",
+            &thumbnail,
+            "-metadata:s:t",
+            mimetype,
+            "-metadata:s:t",
+            filename,
+        ],
+        "mp3" => vec![
+            "-i",
+            &thumbnail,
+            "-map",
+            "0",
+            "-map",
+            "1",
+            "-c",
+            "copy",
+            "-id3v2_version",
+            "3",
+            "-metadata:s:v",
+            "title=Album cover",
+            "-metadata:s:v",
+            "comment=Cover (front)",
+        ],
+        // The cover is the first video stream of audio files
+        // and the second one of videos.
+        _ => vec![
+            "-i",
+            &thumbnail,
+            "-map",
+            "0",
+            "-map",
+            "1",
+            "-c",
+            "copy",
+            if ext == "mp4" {
+                "-disposition:v:1"
+            } else {
+                "-disposition:v:0"
+            },
+            "attached_pic",
+        ],
+    };
+
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    run(&[file.to_path_buf()], &args, file)
+}
+
+// Returns the container that can hold both streams of <pair>, unless
+// the user has chosen one (--merge-output-format).
+pub fn merge_format(pair: &StreamPair, chosen: Option<&str>) -> String {
+    if let Some(chosen) = chosen {
+        return chosen.to_string();
+    }
+
+    match (pair.video.ext.as_str(), pair.audio.ext.as_str()) {
+        ("mp4", "m4a" | "mp4" | "aac") => "mp4",
+        ("webm", "webm" | "opus" | "ogg") => "webm",
+        // Matroska takes (almost) everything.
+        _ => "mkv",
+    }
+    .to_string()
+}
+
+// Muxes <videofile> and <audiofile> into <outputfile>.
+pub fn merge(
+    videofile: &Path,
+    audiofile: &Path,
+    outputfile: &Path,
+    tags: &[(String, String)],
+) -> Result<()> {
+    let audiofile = audiofile.to_string_lossy();
+    let args = with_tags(
+        &[
+            "-i", &audiofile, "-map", "0:v:0", "-map", "1:a:0", "-c", "copy",
+        ],
+        tags,
+    );
+    run(&[videofile.to_path_buf()], &args, outputfile)
+}
+
+// Cuts <duration> seconds (or everything) from <offset> seconds on out of
+// <inputfiles> into <outputfile>. Copying would start at the keyframe
+// before <offset>, so the section is re-encoded to be exact.
+pub fn cut(
+    inputfiles: &[PathBuf],
+    outputfile: &Path,
+    offset: f64,
+    duration: Option<f64>,
+) -> Result<()> {
+    // The inputs are only a few segments longer than the section, so
+    // ffmpeg can decode them from the start.
+    let mut args = vec!["-ss".to_string(), format!("{:.3}", offset)];
+    if let Some(duration) = duration {
+        args.extend(["-t".to_string(), format!("{:.3}", duration)]);
+    }
+    args.extend(
+        [
+            "-map", "0:v?", "-map", "0:a?", "-c:v", "libx264", "-c:a", "aac",
+        ]
+        .map(String::from),
+    );
+    run(inputfiles, &args, outputfile)
+}
+
+// The input options that make ffmpeg fetch <url> the way <agent> would:
+// with the same user agent, headers, cookies, proxy, certificates and
+// local address.
+fn network_args(
+    agent: &YaydlAgent,
+    url: &str,
+    headers: &[(String, String)],
+) -> Result<Vec<String>> {
+    let mut args = vec![];
+    if let Some(user_agent) = agent.user_agent() {
+        args.extend(["-user_agent".to_string(), user_agent.to_string()]);
+    }
+
+    // ffmpeg only knows HTTP proxies, and it must not go around
+    // the one that the user has set.
+    if let Some(proxy) = agent.find_proxy(url) {
+        if !proxy.starts_with("http://") {
+            return Err(anyhow!(
+                "ffmpeg cannot download through the proxy {}, please use an HTTP proxy for --download-sections.",
+                proxy
+            ));
+        }
+        args.extend(["-http_proxy".to_string(), proxy]);
+    }
+
+    let mut header_lines = String::new();
+    for (name, value) in &agent.request_headers(url, headers) {
+        header_lines.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    args.extend(["-headers".to_string(), header_lines]);
+
+    // Unlike yaydl, ffmpeg does not verify certificates by default. The
+    // TLS options must not be given for plain HTTP, ffmpeg rejects them
+    // when nothing uses them.
+    if url.starts_with("https://") {
+        let tls = agent.find_tls_settings();
+        let verify = if tls.no_check_certificate { "0" } else { "1" };
+        args.extend(["-tls_verify".to_string(), verify.to_string()]);
+        if let Some(ca_bundle) = tls.ca_bundle {
+            args.extend([
+                "-ca_file".to_string(),
+                ca_bundle.to_string_lossy().to_string(),
+            ]);
+        }
+        // The certificate and its key are in the same file.
+        if let Some(client_cert) = tls.client_cert {
+            let client_cert = client_cert.to_string_lossy().to_string();
+            args.extend(["-cert_file".to_string(), client_cert.clone()]);
+            args.extend(["-key_file".to_string(), client_cert]);
+        }
+    }
+
+    // Binding to the unspecified address of a family restricts the
+    // connections to that family, too.
+    if let Some(local_address) = agent.local_address() {
+        args.extend(["-local_addr".to_string(), local_address.to_string()]);
+    }
+
+    Ok(args)
+}
+
+// Lets ffmpeg fetch only <section> of the remote <urls> into <outputfile>.
+// Two URLs are a video and an audio stream, they are merged.
+pub fn download_section(
+    agent: &YaydlAgent,
+    urls: &[String],
+    section: &Section,
+    headers: &[(String, String)],
+    outputfile: &Path,
+) -> Result<()> {
+    // ffmpeg would go online for this, there is nothing to replay:
+    if recording::is_replaying() {
+        return Err(anyhow!(
+            "ffmpeg cannot download the section of this video from a replay."
+        ));
+    }
+
+    // These are input options, so every input needs its own. Seeking
+    // in the input skips the download of everything before the section,
+    // and as the streams are re-encoded, ffmpeg starts exactly at it.
+    let mut args = vec![];
+    for url in urls {
+        args.extend(network_args(agent, url, headers)?);
+        args.extend(["-ss".to_string(), format!("{:.3}", section.start)]);
+        args.extend(["-i".to_string(), url.to_string()]);
+    }
+
+    if let Some(duration) = section.duration() {
+        args.extend(["-t".to_string(), format!("{:.3}", duration)]);
+    }
+    if urls.len() > 1 {
+        args.extend(["-map", "0:v:0", "-map", "1:a:0"].map(String::from));
+    } else {
+        args.extend(["-map", "0:v?", "-map", "0:a?"].map(String::from));
+    }
+    run(&[], &args, outputfile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::AgentOptions;
+
+    #[test]
+    fn parses_the_version() {
+        assert_eq!(
+            parse_version("ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023"),
+            Some((6, 1))
+        );
+        assert_eq!(
+            parse_version("ffmpeg version n4.4.2 Copyright"),
+            Some((4, 4))
+        );
+        assert_eq!(parse_version("ffmpeg version 7 Copyright"), Some((7, 0)));
+        // Development builds and other programs:
+        assert_eq!(
+            parse_version("ffmpeg version N-113345-g1e3a8a5 Copyright"),
+            None
+        );
+        assert_eq!(parse_version("avconv version 12.3"), None);
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    fn finds_the_tools_only_where_it_is_told() {
+        let dir = std::env::temp_dir().join(format!("yaydl-ffmpeg-{}-tools", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let error = tools_at(Some(&dir)).err().unwrap();
+        assert!(error.contains(&dir.display().to_string()), "{}", error);
+
+        let ffmpeg = dir.join(format!("ffmpeg{}", env::consts::EXE_SUFFIX));
+        fs::write(&ffmpeg, "").unwrap();
+        let tools = tools_at(Some(&dir)).unwrap();
+        assert_eq!(tools.ffmpeg, ffmpeg);
+        assert_eq!(tools.ffprobe, None);
+
+        // The binary itself, with ffprobe next to it:
+        let ffprobe = dir.join(format!("ffprobe{}", env::consts::EXE_SUFFIX));
+        fs::write(&ffprobe, "").unwrap();
+        let tools = tools_at(Some(&ffmpeg)).unwrap();
+        assert_eq!(tools.ffmpeg, ffmpeg);
+        assert_eq!(tools.ffprobe, Some(ffprobe));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn subtracts_the_section_from_the_duration() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let total = Some(Duration::from_secs(60));
+        assert_eq!(trimmed_duration(total, &[]), total);
+        assert_eq!(
+            trimmed_duration(total, &args(&["-ss", "12.500"])),
+            Some(Duration::from_millis(47_500))
+        );
+        assert_eq!(
+            trimmed_duration(total, &args(&["-ss", "50.000", "-t", "30.000"])),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            trimmed_duration(total, &args(&["-ss", "10.000", "-t", "30.000"])),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            trimmed_duration(total, &args(&["-ss", "90"])),
+            Some(Duration::ZERO)
+        );
+        // Remote inputs: only the length of the section is known.
+        assert_eq!(
+            trimmed_duration(None, &args(&["-ss", "10.000", "-t", "5.000"])),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(trimmed_duration(None, &args(&["-ss", "10.000"])), None);
+    }
+
+    #[test]
+    fn passes_the_network_settings_to_ffmpeg() {
+        let agent = YaydlAgent::new(AgentOptions {
+            user_agent: Some("yaydl-test".to_string()),
+            no_proxy: true,
+            no_check_certificate: true,
+            source_address: Some("192.0.2.7".parse().unwrap()),
+            ..AgentOptions::default()
+        })
+        .unwrap();
+        let headers = [("Referer".to_string(), "https://ffmpeg.test/".to_string())];
+        let args = network_args(&agent, "https://ffmpeg.test/video.mp4", &headers).unwrap();
+        assert_eq!(
+            args,
+            [
+                "-user_agent",
+                "yaydl-test",
+                "-headers",
+                "Referer: https://ffmpeg.test/\r\n",
+                "-tls_verify",
+                "0",
+                "-local_addr",
+                "192.0.2.7",
+            ]
+        );
+
+        // Certificates are checked; plain HTTP gets no TLS options, and
+        // forcing IPv6 binds to "::".
+        let agent = YaydlAgent::new(AgentOptions {
+            no_proxy: true,
+            force_ipv6: true,
+            ..AgentOptions::default()
+        })
+        .unwrap();
+        let args = network_args(&agent, "https://ffmpeg.test/video.mp4", &[]).unwrap();
+        assert!(args.windows(2).any(|arg| arg == ["-tls_verify", "1"]));
+        assert!(args.windows(2).any(|arg| arg == ["-local_addr", "::"]));
+        let args = network_args(&agent, "http://ffmpeg.test/video.mp4", &[]).unwrap();
+        assert!(!args.iter().any(|arg| arg == "-tls_verify"));
+
+        // Only HTTP proxies work:
+        let agent = YaydlAgent::new(AgentOptions {
+            proxy: Some("socks5://127.0.0.1:1080".to_string()),
+            ..AgentOptions::default()
+        })
+        .unwrap();
+        assert!(network_args(&agent, "https://ffmpeg.test/video.mp4", &[]).is_err());
+    }
+
+    #[test]
+    fn parses_the_audio_quality() {
+        assert!(matches!(parse_audio_quality("0"), Ok(AudioQuality::Vbr(0))));
+        assert!(matches!(
+            parse_audio_quality("10"),
+            Ok(AudioQuality::Vbr(10))
+        ));
+        assert!(matches!(
+            parse_audio_quality("128K"),
+            Ok(AudioQuality::Bitrate(128))
+        ));
+        assert!(matches!(
+            parse_audio_quality("320k"),
+            Ok(AudioQuality::Bitrate(320))
+        ));
+        for quality in ["11", "-1", "0K", "K", "fast", "1.5"] {
+            assert!(parse_audio_quality(quality).is_err(), "{}", quality);
+        }
+    }
+
+    #[test]
+    fn maps_the_audio_quality_to_each_encoder() {
+        let args = |format: &str, quality: &str| {
+            let encoder = audio_codec(format).unwrap().encoder;
+            quality_args(encoder, parse_audio_quality(quality).unwrap()).join(" ")
+        };
+        assert_eq!(args("mp3", "2"), "-q:a 2");
+        assert_eq!(args("mp3", "10"), "-q:a 9");
+        assert_eq!(args("vorbis", "0"), "-q:a 10");
+        assert_eq!(args("m4a", "0"), "-b:a 256k");
+        assert_eq!(args("opus", "10"), "-b:a 48k");
+        assert_eq!(args("mp3", "192K"), "-b:a 192k");
+        assert_eq!(args("flac", "0"), "");
+        assert_eq!(audio_extension("vorbis"), "ogg");
+
+        // Only untouched audio can be copied without an encoder:
+        let mut options = AudioOptions {
+            format: "mp3".to_string(),
+            quality: None,
+            sample_rate: None,
+            channels: None,
+        };
+        assert!(options.can_copy());
+        options.channels = Some(1);
+        assert!(!options.can_copy());
+        assert_eq!(audio_extension("best"), "best");
+    }
 }
@@ -16,14 +16,13 @@
 // Yet Another Youtube Down Loader
 // - single video file handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
+use crate::agent::YaydlAgent;
 use crate::definitions::SiteDefinition;
 use crate::VIDEO;
 
 use anyhow::Result;
 use regex::Regex;
 use std::path::Path;
-use url::Url;
 
 // Implement the site definition:
 struct GenericFileHandler;
@@ -31,6 +30,7 @@ impl SiteDefinition for GenericFileHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
@@ -45,6 +45,7 @@ impl SiteDefinition for GenericFileHandler {
     fn find_video_title<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
@@ -61,6 +62,7 @@ impl SiteDefinition for GenericFileHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -72,25 +74,24 @@ impl SiteDefinition for GenericFileHandler {
     fn does_video_exist<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let url_p = Url::parse(url)?;
-        let agent = YaydlAgent::init(url_p);
-
         match agent.get(url).call() {
             Ok(_) => Ok(true),
             Err(_) => Ok(false),
         }
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn display_name(&self) -> String {
         "(direct)".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -99,7 +100,7 @@ impl SiteDefinition for GenericFileHandler {
         Ok(String::from(url).split(".").last().unwrap().to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
 }
@@ -108,3 +109,40 @@ impl SiteDefinition for GenericFileHandler {
 inventory::submit! {
     &GenericFileHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_a_video_file() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://media.example.com/clips/sunset-timelapse.mp4";
+        let handler = GenericFileHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "sunset-timelapse"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            url
+        );
+        assert_eq!(
+            handler
+                .find_video_file_extension(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "mp4"
+        );
+    }
+}
@@ -16,7 +16,9 @@
 // Yet Another Youtube Down Loader
 // - PornDoe handler -
 
-use crate::definitions::SiteDefinition;
+use crate::agent::YaydlAgent;
+use crate::definitions::{SiteDefinition, VideoMetadata};
+use crate::recording;
 use crate::VIDEO;
 
 use anyhow::{anyhow, Result};
@@ -29,6 +31,12 @@ fn get_video_info(video: &mut VIDEO, url: &str, webdriver_port: u16) -> Result<b
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // It will contain the whole body for now.
+        // Replays come without a web driver:
+        if recording::is_replaying() {
+            video.info = recording::replay_page(url)?;
+            return Ok(true);
+        }
+
         let local_url = url.to_owned();
 
         let rt = runtime::Builder::new_current_thread()
@@ -36,7 +44,7 @@ fn get_video_info(video: &mut VIDEO, url: &str, webdriver_port: u16) -> Result<b
             .enable_io()
             .build()
             .unwrap();
-        rt.block_on(async move {
+        let body = rt.block_on(async move {
             let webdriver_url = format!("http://localhost:{}", webdriver_port);
             let c = ClientBuilder::native()
                 .connect(&webdriver_url)
@@ -55,9 +63,12 @@ fn get_video_info(video: &mut VIDEO, url: &str, webdriver_port: u16) -> Result<b
             .expect("could not dismiss the age gate");
 
             let body = c.source().await.expect("could not read the site source");
-            video.info.push_str(body.as_str());
             c.close_window().await.expect("could not close the window");
+            body
         });
+
+        recording::record_page(url, &body);
+        video.info.push_str(body.as_str());
     }
 
     Ok(true)
@@ -69,6 +80,7 @@ impl SiteDefinition for PornDoeHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
@@ -83,6 +95,7 @@ impl SiteDefinition for PornDoeHandler {
     fn find_video_title<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
     ) -> Result<String> {
@@ -103,6 +116,7 @@ impl SiteDefinition for PornDoeHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
         _onlyaudio: bool,
@@ -120,6 +134,7 @@ impl SiteDefinition for PornDoeHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
     ) -> Result<bool> {
@@ -127,13 +142,25 @@ impl SiteDefinition for PornDoeHandler {
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn find_video_metadata<'a>(
+        &'a self,
+        video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
+        url: &'a str,
+        webdriver_port: u16,
+    ) -> Result<VideoMetadata> {
+        let _ = get_video_info(video, url, webdriver_port)?;
+        Ok(VideoMetadata::from_html(&Html::parse_document(&video.info)))
+    }
+
+    fn display_name(&self) -> String {
         "PornDoe".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -141,7 +168,7 @@ impl SiteDefinition for PornDoeHandler {
         Ok("mp4".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         true
     }
 }
@@ -150,3 +177,41 @@ impl SiteDefinition for PornDoeHandler {
 inventory::submit! {
     &PornDoeHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_video_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://porndoe.com/watch/ab12cd34ef";
+        let handler = PornDoeHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "Harbour Walk"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://v.porndoe.com/media/ab12cd34ef/720p.mp4?t=1760800000"
+        );
+        let metadata = handler
+            .find_video_metadata(&mut video, &agent, url, 0)
+            .unwrap();
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("A quiet walk along the harbour.")
+        );
+    }
+}
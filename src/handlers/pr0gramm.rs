@@ -16,16 +16,17 @@
 // Yet Another Youtube Down Loader
 // - pr0gramm handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
+use crate::agent::YaydlAgent;
+use crate::credentials::Credentials;
 use crate::definitions::SiteDefinition;
 use crate::VIDEO;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use regex::Regex;
 use scraper::{Html, Selector};
-use url::Url;
+use serde_json::Value;
 
-fn get_video_info(video: &mut VIDEO, url: &str) -> Result<Html> {
+fn get_video_info(video: &mut VIDEO, agent: &YaydlAgent, url: &str) -> Result<Html> {
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // It will contain the whole body for now.
@@ -35,16 +36,9 @@ fn get_video_info(video: &mut VIDEO, url: &str) -> Result<Html> {
 
         let static_url = format!("https://pr0gramm.com/static/{}", id).to_owned();
 
-        let url_p = Url::parse(&static_url)?;
-        let agent = YaydlAgent::init(url_p);
-
         let body = agent
-            .get(&static_url)
-            .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+            .get_page(&static_url)
+            .expect("Could not go to the url");
         video.info.push_str(&body);
     }
 
@@ -59,6 +53,7 @@ impl SiteDefinition for Pr0grammHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
@@ -72,10 +67,11 @@ impl SiteDefinition for Pr0grammHandler {
     fn find_video_title<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
-        let video_info = get_video_info(video, url)?;
+        let video_info = get_video_info(video, agent, url)?;
 
         let title_selector = Selector::parse("title").unwrap();
         let text = video_info.select(&title_selector).next();
@@ -90,11 +86,12 @@ impl SiteDefinition for Pr0grammHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
     ) -> Result<String> {
-        let video_info = get_video_info(video, url)?;
+        let video_info = get_video_info(video, agent, url)?;
 
         let url_selector = Selector::parse("video").unwrap();
         let url_elem = video_info.select(&url_selector).next().unwrap();
@@ -109,20 +106,47 @@ impl SiteDefinition for Pr0grammHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _video_info = get_video_info(video, url);
+        let _video_info = get_video_info(video, agent, url);
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn display_name(&self) -> String {
         "pr0gramm".to_string()
     }
 
+    fn login(&self, agent: &YaydlAgent, credentials: &Credentials) -> Result<bool> {
+        // Anonymous users only see SFW items. The login API answers
+        // with {"success": true/false, "error": ...} and sets the
+        // session cookie on success.
+        let response: Value = agent
+            .post("https://pr0gramm.com/api/user/login")
+            .config()
+            .http_status_as_error(false)
+            .build()
+            .send_form([
+                ("name", credentials.username.as_str()),
+                ("password", credentials.password.as_str()),
+            ])?
+            .body_mut()
+            .read_json()?;
+
+        if response["success"].as_bool() == Some(true) {
+            return Ok(true);
+        }
+        Err(anyhow!(
+            "Could not log in to pr0gramm: {}",
+            response["error"].as_str().unwrap_or("unknown error")
+        ))
+    }
+
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -130,7 +154,7 @@ impl SiteDefinition for Pr0grammHandler {
         Ok("mp4".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
 }
@@ -139,3 +163,34 @@ impl SiteDefinition for Pr0grammHandler {
 inventory::submit! {
     &Pr0grammHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_video_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://pr0gramm.com/user/kartonfan/uploads/5871234";
+        let handler = Pr0grammHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "pr0gramm.com - Katze im Karton"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://vid.pr0gramm.com/2024/05/11/7e1a9c0d2b3f4e51.mp4"
+        );
+    }
+}
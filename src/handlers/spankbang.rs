@@ -26,8 +26,8 @@
 // Yet Another Youtube Down Loader
 // - Spankbang handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
-use crate::definitions::SiteDefinition;
+use crate::agent::YaydlAgent;
+use crate::definitions::{SiteDefinition, VideoMetadata};
 use crate::VIDEO;
 
 use anyhow::Result;
@@ -37,23 +37,13 @@ use url::Url;
 
 const MAX_FILENAME_LENGTH: usize = 142; // filename is based on url path description string
 
-fn get_video_info(video: &mut VIDEO, url: &str) -> Result<bool> {
+fn get_video_info(video: &mut VIDEO, agent: &YaydlAgent, url: &str) -> Result<bool> {
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // It will contain the whole body for now.
         let local_url = url.to_owned();
 
-        // Initialize the agent:
-        let url_p = Url::parse(&local_url)?;
-        let agent = YaydlAgent::init(url_p);
-
-        let body = agent
-            .get(&local_url)
-            .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+        let body = agent.get_page(&local_url).expect("Could not go to the url");
         video.info.push_str(&body);
     }
 
@@ -66,6 +56,7 @@ impl SiteDefinition for SpankbangHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
@@ -80,6 +71,7 @@ impl SiteDefinition for SpankbangHandler {
     fn find_video_title<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
@@ -91,11 +83,12 @@ impl SiteDefinition for SpankbangHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
     ) -> Result<String> {
-        let _not_used = get_video_info(video, url);
+        let _not_used = get_video_info(video, agent, url);
         let video_info_html = Html::parse_document(&video.info);
 
         let url_selector = Selector::parse(r#"source[type="video/mp4"]"#).unwrap();
@@ -108,20 +101,33 @@ impl SiteDefinition for SpankbangHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _not_used = get_video_info(video, url);
+        let _not_used = get_video_info(video, agent, url);
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn find_video_metadata<'a>(
+        &'a self,
+        video: &'a mut VIDEO,
+        agent: &YaydlAgent,
+        url: &'a str,
+        _webdriver_port: u16,
+    ) -> Result<VideoMetadata> {
+        let _ = get_video_info(video, agent, url)?;
+        Ok(VideoMetadata::from_html(&Html::parse_document(&video.info)))
+    }
+
+    fn display_name(&self) -> String {
         "Spankbang".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -129,7 +135,7 @@ impl SiteDefinition for SpankbangHandler {
         Ok("mp4".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
 }
@@ -153,7 +159,7 @@ fn url_filename(url: String) -> String {
     };
 
     // path = /12345/video/description+for+this+video
-    let vec: Vec<&str> = path.as_str().split("/").map(|s| s).collect();
+    let vec: Vec<&str> = path.as_str().split("/").collect();
     let id_5char = vec[1]; // = 12345
     let description = vec[3]; // use for base filename, = description+for+this+video
 
@@ -164,32 +170,53 @@ fn url_filename(url: String) -> String {
         base_filename = format!("{}...-{}", shorten, id_5char); // concat using shorten description
     }
 
-    return windows_filename(linux_filename(base_filename));
+    windows_filename(linux_filename(base_filename))
 }
 
 // replace invalid linux chars with _ underscore
 fn linux_filename(in_filename: String) -> String {
-    let out_filename = format!(
-        "{}",
-        in_filename.trim().replace(
-            &['|', '\'', '\"', ':', '\'', '\\', '/'][..], // '"', also works for quote char
-            r#"_"#
-        )
-    );
-
-    return out_filename;
+    in_filename.trim().replace(
+        &['|', '\'', '\"', ':', '\'', '\\', '/'][..], // '"', also works for quote char
+        r#"_"#,
+    )
 }
 
 // replace invalid windows chars with _ underscore
 fn windows_filename(in_filename: String) -> String {
-    let out_filename = format!(
-        "{}",
-        in_filename
-            .trim()
-            // also replace newline char
-            // replacing plus '+' char is specific to spankbang
-            .replace(&['<', '>', ':', '?', '*', '\n', '+'][..], r#"_"#) // replace with underscore char
-    );
-
-    return out_filename;
+    in_filename
+        .trim()
+        // also replace newline char
+        // replacing plus '+' char is specific to spankbang
+        .replace(&['<', '>', ':', '?', '*', '\n', '+'][..], r#"_"#) // replace with underscore char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_video_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://spankbang.com/8x7k2/video/evening+stroll";
+        let handler = SpankbangHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "evening_stroll-8x7k2"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://vdownload-12.sb-cd.com/8/x/8x7k2-720p.mp4?secure=Xx1-aBc&m=12"
+        );
+    }
 }
@@ -16,30 +16,20 @@
 // Yet Another Youtube Down Loader
 // - Vidoza handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
+use crate::agent::YaydlAgent;
 use crate::definitions::SiteDefinition;
 use crate::VIDEO;
 
 use anyhow::Result;
 use regex::Regex;
 use scraper::{Html, Selector};
-use url::Url;
 
-fn get_video_info(video: &mut VIDEO, url: &str) -> Result<Html> {
+fn get_video_info(video: &mut VIDEO, agent: &YaydlAgent, url: &str) -> Result<Html> {
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // It will contain the whole body for now.
-        // Initialize the agent:
-        let url_p = Url::parse(url)?;
-        let agent = YaydlAgent::init(url_p);
-
-        let body = agent
-            .get(url)
-            .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+
+        let body = agent.get_page(url).expect("Could not go to the url");
 
         video.info = body;
     }
@@ -55,6 +45,7 @@ impl SiteDefinition for VidozaHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
@@ -69,10 +60,11 @@ impl SiteDefinition for VidozaHandler {
     fn find_video_title<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
-        let video_info = get_video_info(video, url)?;
+        let video_info = get_video_info(video, agent, url)?;
 
         // Currently, there only is one <H1> on Vidoza. Good for us.
         let h1_selector = Selector::parse("h1").unwrap();
@@ -88,11 +80,12 @@ impl SiteDefinition for VidozaHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
     ) -> Result<String> {
-        let video_info = get_video_info(video, url)?;
+        let video_info = get_video_info(video, agent, url)?;
 
         let url_selector = Selector::parse("source").unwrap();
         let url_elem = video_info.select(&url_selector).next().unwrap();
@@ -104,20 +97,22 @@ impl SiteDefinition for VidozaHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _video_info = get_video_info(video, url);
+        let _video_info = get_video_info(video, agent, url);
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn display_name(&self) -> String {
         "Vidoza".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -125,7 +120,7 @@ impl SiteDefinition for VidozaHandler {
         Ok("mp4".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
 }
@@ -134,3 +129,34 @@ impl SiteDefinition for VidozaHandler {
 inventory::submit! {
     &VidozaHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_video_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://vidoza.net/embed-q8w7e6r5t4y3.html";
+        let handler = VidozaHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "Mountain Lake 4K"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://str28.vidoza.net/nvl4c7zjmkbmzdehbbpkwvt7ivbmquqoypkzymawqtdvqrbu3tmiqa/v.mp4"
+        );
+    }
+}
@@ -16,30 +16,24 @@
 // Yet Another Youtube Down Loader
 // - Vimeo handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
-use crate::definitions::SiteDefinition;
+use crate::agent::YaydlAgent;
+use crate::credentials::Credentials;
+use crate::definitions::{SiteDefinition, Stream, StreamPair, VideoMetadata};
 use crate::VIDEO;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use m3u8_rs::{AlternativeMediaType, Playlist};
 use regex::Regex;
 use serde_json::Value;
 use url::Url;
 
-fn get_video_info(video: &mut VIDEO, url: &str) -> Result<Value> {
+fn get_video_info(video: &mut VIDEO, agent: &YaydlAgent, url: &str) -> Result<Value> {
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // Those are hidden behing a config file defined in the page source code.
         // Search for: window.vimeo.clip_page_config.player = {"config_url":"(.+?)"
-        let url_p = Url::parse(url)?;
-        let agent = YaydlAgent::init(url_p);
 
-        let body = agent
-            .get(url)
-            .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+        let body = agent.get_page(url).expect("Could not go to the url");
         let re =
             Regex::new("window.vimeo.clip_page_config.player = .\"config_url\":\"(?P<URL>.+?)\"")
                 .unwrap();
@@ -63,12 +57,8 @@ fn get_video_info(video: &mut VIDEO, url: &str) -> Result<Value> {
         // The "config_url" body is a JSON structure.
         // Grab and store it:
         let config_body = agent
-            .get(&video_info_url)
-            .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+            .get_page(&video_info_url)
+            .expect("Could not go to the url");
         video.info.push_str(config_body.as_str());
     }
 
@@ -83,6 +73,7 @@ impl SiteDefinition for VimeoHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
@@ -97,6 +88,7 @@ impl SiteDefinition for VimeoHandler {
     fn find_video_title<'a>(
         &'a self,
         video: &mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
@@ -107,13 +99,14 @@ impl SiteDefinition for VimeoHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
     ) -> Result<String> {
         let id_regex = Regex::new(r"(?:vimeo.com/)(.*$)").unwrap();
         let id = id_regex.captures(url).unwrap().get(1).unwrap().as_str();
-        let video_info = get_video_info(video, id)?;
+        let video_info = get_video_info(video, agent, id)?;
         let video_info_streams_progressive =
             match video_info["request"]["files"]["progressive"].as_array() {
                 None => return Ok("".to_string()),
@@ -138,20 +131,163 @@ impl SiteDefinition for VimeoHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _video_info = get_video_info(video, url);
+        let _video_info = get_video_info(video, agent, url);
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn find_video_metadata<'a>(
+        &'a self,
+        video: &'a mut VIDEO,
+        agent: &YaydlAgent,
+        url: &'a str,
+        _webdriver_port: u16,
+    ) -> Result<VideoMetadata> {
+        let video_info = get_video_info(video, agent, url)?;
+
+        // The thumbnails are listed by their width, plus a "base" one.
+        // Take the widest:
+        let thumbnail = video_info["video"]["thumbs"]
+            .as_object()
+            .and_then(|thumbs| {
+                thumbs
+                    .iter()
+                    .filter_map(|(width, url)| Some((width.parse::<u32>().ok()?, url.as_str()?)))
+                    .max_by_key(|(width, _)| *width)
+            })
+            .map(|(_, url)| url.to_string());
+
+        Ok(VideoMetadata {
+            uploader: video_info["video"]["owner"]["name"]
+                .as_str()
+                .map(str::to_string),
+            thumbnail,
+            ..VideoMetadata::default()
+        })
+    }
+
+    fn find_stream_pair<'a>(
+        &'a self,
+        video: &'a mut VIDEO,
+        agent: &YaydlAgent,
+        url: &'a str,
+        _webdriver_port: u16,
+    ) -> Result<Option<StreamPair>> {
+        // The HLS master playlist has the best video without audio and
+        // the audio in a separate rendition.
+        let video_info = get_video_info(video, agent, url)?;
+        let hls = &video_info["request"]["files"]["hls"];
+        let default_cdn = hls["default_cdn"].as_str().unwrap_or("");
+        let Some(master_url) = hls["cdns"][default_cdn]["url"].as_str() else {
+            return Ok(None);
+        };
+
+        let body = agent.get_page(master_url)?;
+        let master = match m3u8_rs::parse_playlist_res(body.as_bytes()) {
+            Ok(Playlist::MasterPlaylist(master)) => master,
+            _ => return Ok(None),
+        };
+
+        let Some(variant) = master.variants.iter().max_by_key(|v| v.bandwidth) else {
+            return Ok(None);
+        };
+        let Some(group_id) = &variant.audio else {
+            return Ok(None);
+        };
+        let audio = master
+            .alternatives
+            .iter()
+            .filter(|a| {
+                a.media_type == AlternativeMediaType::Audio
+                    && &a.group_id == group_id
+                    && a.uri.is_some()
+            })
+            .max_by_key(|a| a.default);
+        let Some(audio_uri) = audio.and_then(|a| a.uri.as_ref()) else {
+            return Ok(None);
+        };
+
+        let master_url = Url::parse(master_url)?;
+        Ok(Some(StreamPair {
+            video: Stream {
+                url: master_url.join(&variant.uri)?.to_string(),
+                ext: "mp4".to_string(),
+                is_playlist: true,
+            },
+            audio: Stream {
+                url: master_url.join(audio_uri)?.to_string(),
+                ext: "m4a".to_string(),
+                is_playlist: true,
+            },
+        }))
+    }
+
+    fn display_name(&self) -> String {
         "Vimeo".to_string()
     }
 
+    fn login(&self, agent: &YaydlAgent, credentials: &Credentials) -> Result<bool> {
+        // The login form needs the XSRF token of the current session:
+        let viewer: Value = agent
+            .get("https://vimeo.com/_next/viewer")
+            .call()?
+            .body_mut()
+            .read_json()?;
+        let xsrft = viewer["xsrft"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Could not find Vimeo's login token."))?;
+
+        // A successful login redirects. Do not follow it, the session
+        // cookies are set by this response.
+        let response = agent
+            .post("https://vimeo.com/log_in")
+            .header("Referer", "https://vimeo.com/log_in")
+            .config()
+            .max_redirects(0)
+            .http_status_as_error(false)
+            .build()
+            .send_form([
+                ("action", "login"),
+                ("email", credentials.username.as_str()),
+                ("password", credentials.password.as_str()),
+                ("service", "vimeo"),
+                ("token", xsrft),
+            ])?;
+
+        // Failed logins often show the form again with a 200, so only a
+        // redirect away from it that starts a session ("vimeo" cookie)
+        // counts:
+        let location = response
+            .headers()
+            .get("Location")
+            .and_then(|location| location.to_str().ok())
+            .unwrap_or_default();
+        let has_session = response
+            .headers()
+            .get_all("Set-Cookie")
+            .iter()
+            .filter_map(|cookie| cookie.to_str().ok())
+            .any(|cookie| cookie.starts_with("vimeo="));
+
+        match response.status().as_u16() {
+            300..=399 if has_session && !location.contains("/log_in") => Ok(true),
+            418 => Err(anyhow!(
+                "Could not log in to Vimeo: wrong username or password."
+            )),
+            200..=399 => Err(anyhow!(
+                "Could not log in to Vimeo: no session was started. Please check your username and password."
+            )),
+            status => Err(anyhow!("Could not log in to Vimeo (HTTP {}).", status)),
+        }
+    }
+
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -159,7 +295,7 @@ impl SiteDefinition for VimeoHandler {
         Ok("mp4".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
 }
@@ -168,3 +304,56 @@ impl SiteDefinition for VimeoHandler {
 inventory::submit! {
     &VimeoHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_streams_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://vimeo.com/76979871";
+        let handler = VimeoHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "The New Vimeo Player (You Know, For Videos)"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://vod-progressive.akamaized.net/exp=1760803600/76979871/720p.mp4"
+        );
+
+        let pair = handler
+            .find_stream_pair(&mut video, &agent, url, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            pair.video.url,
+            "https://vod-adaptive-ak.vimeocdn.com/exp=1760803600~hmac=4f0e/76979871/video/720p/playlist.m3u8"
+        );
+        assert_eq!(
+            pair.audio.url,
+            "https://vod-adaptive-ak.vimeocdn.com/exp=1760803600~hmac=4f0e/76979871/audio/128000/playlist.m3u8"
+        );
+
+        let metadata = handler
+            .find_video_metadata(&mut video, &agent, url, 0)
+            .unwrap();
+        assert_eq!(metadata.uploader.as_deref(), Some("Vimeo Staff"));
+        assert_eq!(
+            metadata.thumbnail.as_deref(),
+            Some("https://i.vimeocdn.com/video/452001751-1280.jpg")
+        );
+    }
+}
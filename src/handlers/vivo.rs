@@ -16,7 +16,7 @@
 // Yet Another Youtube Down Loader
 // - VIVO handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
+use crate::agent::YaydlAgent;
 use crate::definitions::SiteDefinition;
 use crate::VIDEO;
 
@@ -24,24 +24,14 @@ use anyhow::Result;
 use cienli::ciphers::rot::{Rot, RotType};
 use regex::Regex;
 use scraper::{Html, Selector};
-use url::Url;
 use urlencoding::decode;
 
-fn get_video_info(video: &mut VIDEO, url: &str) -> Result<Html> {
+fn get_video_info(video: &mut VIDEO, agent: &YaydlAgent, url: &str) -> Result<Html> {
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // It will contain the whole body for now.
-        // Initialize the agent:
-        let url_p = Url::parse(&url)?;
-        let agent = YaydlAgent::init(url_p);
-
-        let body = agent
-            .get(url)
-            .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+
+        let body = agent.get_page(url).expect("Could not go to the url");
         video.info.push_str(body.as_str());
     }
 
@@ -55,6 +45,7 @@ impl SiteDefinition for VivoHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
@@ -69,10 +60,11 @@ impl SiteDefinition for VivoHandler {
     fn find_video_title<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
-        let video_info = get_video_info(video, url)?;
+        let video_info = get_video_info(video, agent, url)?;
 
         let title_selector = Selector::parse("div.stream-content").unwrap();
         let title_elem = video_info.select(&title_selector).next().unwrap();
@@ -84,6 +76,7 @@ impl SiteDefinition for VivoHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -108,20 +101,22 @@ impl SiteDefinition for VivoHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _video_info = get_video_info(video, url);
+        let _video_info = get_video_info(video, agent, url);
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn display_name(&self) -> String {
         "VIVO".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -129,7 +124,7 @@ impl SiteDefinition for VivoHandler {
         Ok("mp4".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
 }
@@ -138,3 +133,34 @@ impl SiteDefinition for VivoHandler {
 inventory::submit! {
     &VivoHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_video_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://vivo.sx/f3e2d1c0b9";
+        let handler = VivoHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "Rainy Rooftops.mp4"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://node-38.vivo.sx/get/f3e2d1c0b9/clip.mp4"
+        );
+    }
+}
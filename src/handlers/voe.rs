@@ -16,22 +16,16 @@
 // Yet Another Youtube Down Loader
 // - VOE handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
+use crate::agent::YaydlAgent;
 use crate::definitions::SiteDefinition;
 use crate::VIDEO;
 
 use anyhow::Result;
 use regex::Regex;
 use scraper::{Html, Selector};
-use url::Url;
 
-fn resolve_js_redirect(url: &str) -> Result<String> {
+fn resolve_js_redirect(agent: &YaydlAgent, url: &str) -> Result<String> {
     // VOE tends to redirect. Find the actual target URL:
-    let static_url = url.to_owned();
-
-    let url_p = Url::parse(&static_url).unwrap();
-    let agent = YaydlAgent::init(url_p);
-
     // We need to fail here if anything goes wrong.
     // To avoid conflicts with the generic download handler, we
     // call this method right in the can_handle_url() method.
@@ -39,7 +33,7 @@ fn resolve_js_redirect(url: &str) -> Result<String> {
     // (tried to be) read here. As that will force a
     // BodyExceedsLimit error, we'll just return a "nope", so
     // the fallback to other handlers is considered.
-    let body = agent.get(url).call()?.body_mut().read_to_string()?;
+    let body = agent.get_page(url)?;
 
     let re_redirect = Regex::new(r"window.location.href = '(?P<URL>.*?)'").unwrap();
     if !re_redirect.is_match(&body) {
@@ -53,20 +47,12 @@ fn resolve_js_redirect(url: &str) -> Result<String> {
     }
 }
 
-fn get_video_info(video: &mut VIDEO, url: &str) -> Result<Html> {
+fn get_video_info(video: &mut VIDEO, agent: &YaydlAgent, url: &str) -> Result<Html> {
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // It will contain the whole body for now.
-        let url_p = Url::parse(url)?;
-        let agent = YaydlAgent::init(url_p);
-
-        let body = agent
-            .get(url)
-            .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+
+        let body = agent.get_page(url).expect("Could not go to the url");
         video.info.push_str(&body);
     }
 
@@ -81,17 +67,15 @@ impl SiteDefinition for VoeHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
         // We need to catch both VOE.sx and whatever redirectors it uses.
         // As main.rs hasn't built the VIDEO struct here yet, we'll parse
         // the resulting website a first time...
-        let url_p = Url::parse(url)?;
-        let agent = YaydlAgent::init(url_p);
-
-        let redir_url = &resolve_js_redirect(&url)?;
-        let body = agent.get(redir_url).call()?.body_mut().read_to_string()?;
+        let redir_url = &resolve_js_redirect(agent, url)?;
+        let body = agent.get_page(redir_url)?;
 
         // If the body contains a VOEPlayer, we're in it.
         Ok(Regex::new(r"VOEPlayer").unwrap().is_match(&body))
@@ -104,10 +88,11 @@ impl SiteDefinition for VoeHandler {
     fn find_video_title<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
-        let video_info = get_video_info(video, url)?;
+        let video_info = get_video_info(video, agent, url)?;
 
         let h1_selector = Selector::parse("h1.mt-1").unwrap();
         let text = video_info.select(&h1_selector).next();
@@ -125,11 +110,12 @@ impl SiteDefinition for VoeHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
     ) -> Result<String> {
-        let _video_info = get_video_info(video, url)?;
+        let _video_info = get_video_info(video, agent, url)?;
         let url_re = Regex::new(r#"Node", "(?P<URL>[^"]+)"#).unwrap();
         let url_search = url_re.captures(&video.info).unwrap();
         let video_url = url_search.name("URL").map_or("", |u| u.as_str());
@@ -140,20 +126,22 @@ impl SiteDefinition for VoeHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _video_info = get_video_info(video, url);
+        let _video_info = get_video_info(video, agent, url);
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn display_name(&self) -> String {
         "Voe".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -161,12 +149,48 @@ impl SiteDefinition for VoeHandler {
         Ok("mp4".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
+
+    fn http_headers(&self, url: &str) -> Vec<(String, String)> {
+        // The delivery nodes want to see the embedding page.
+        vec![("Referer".to_string(), url.to_string())]
+    }
 }
 
 // Push the site definition to the list of known handlers:
 inventory::submit! {
     &VoeHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_video_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://voe.sx/e/v0e7x2k9m4pq";
+        let handler = VoeHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "Ocean Waves"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://delivery-node-4kqc9.voe-network.net/engine/hls2/01/08123/v0e7x2k9m4pq_,n,.urlset/master.m3u8?t=Tk2a"
+        );
+    }
+}
@@ -16,8 +16,8 @@
 // Yet Another Youtube Down Loader
 // - xHamster handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
-use crate::definitions::SiteDefinition;
+use crate::agent::YaydlAgent;
+use crate::definitions::{SiteDefinition, VideoMetadata};
 use crate::VIDEO;
 
 use anyhow::{anyhow, Result};
@@ -25,21 +25,15 @@ use nom::Finish;
 use scraper::{Html, Selector};
 use url::Url;
 
-fn get_video_info(video: &mut VIDEO, url: &str) -> Result<bool> {
+fn get_video_info(video: &mut VIDEO, agent: &YaydlAgent, url: &str) -> Result<bool> {
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // It will contain the whole body for now.
-        let url_p = Url::parse(url)?;
-        let agent = YaydlAgent::init(url_p);
 
+        // Every URL ends up here first, so other handlers' URLs must
+        // not make this fail hard:
         let local_url = url.to_owned();
-        let body = agent
-            .get(&local_url)
-            .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+        let body = agent.get_page(&local_url)?;
         video.info.push_str(&body);
     }
 
@@ -52,10 +46,11 @@ impl SiteDefinition for XHamsterHandler {
     fn can_handle_url<'a>(
         &'a self,
         video: &mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _not_used = get_video_info(video, url)?;
+        let _not_used = get_video_info(video, agent, url)?;
         let video_info_html = Html::parse_document(video.info.as_str());
 
         // xHamster URLs contain application-name=="xHamster".
@@ -82,10 +77,11 @@ impl SiteDefinition for XHamsterHandler {
     fn find_video_title<'a>(
         &'a self,
         video: &mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
-        let _ = get_video_info(video, url)?;
+        let _ = get_video_info(video, agent, url)?;
         let video_info_html = Html::parse_document(video.info.as_str());
 
         let h1_selector = Selector::parse("h1").unwrap();
@@ -102,32 +98,26 @@ impl SiteDefinition for XHamsterHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
     ) -> Result<String> {
-        let _not_used = get_video_info(video, url)?;
+        let _not_used = get_video_info(video, agent, url)?;
         let video_info_html = Html::parse_document(video.info.as_str());
 
-        let url_p = Url::parse(url)?;
-        let agent = YaydlAgent::init(url_p);
-
         // Find the playlist first:
         let url_selector = Selector::parse(r#"link[rel="preload"][as="fetch"]"#).unwrap();
         let url_elem = video_info_html.select(&url_selector).next().unwrap();
         let url_contents = url_elem.value().attr("href").unwrap();
 
         let mut playlist_url = Url::parse(url_contents)?;
-        let request = agent.get(playlist_url.as_str());
-        let playlist_text = request
-            .call()
-            .expect("Could not go to the playlist url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the playlist source");
+        let playlist_text = agent
+            .get_page(playlist_url.as_str())
+            .expect("Could not go to the playlist url");
 
         // Parse the playlist:
-        let playlist = m3u8_rs::parse_media_playlist(&playlist_text.as_bytes())
+        let playlist = m3u8_rs::parse_media_playlist(playlist_text.as_bytes())
             .finish()
             .unwrap();
 
@@ -148,20 +138,33 @@ impl SiteDefinition for XHamsterHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _video_info = get_video_info(video, url);
+        let _video_info = get_video_info(video, agent, url);
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn find_video_metadata<'a>(
+        &'a self,
+        video: &'a mut VIDEO,
+        agent: &YaydlAgent,
+        url: &'a str,
+        _webdriver_port: u16,
+    ) -> Result<VideoMetadata> {
+        let _ = get_video_info(video, agent, url)?;
+        Ok(VideoMetadata::from_html(&Html::parse_document(&video.info)))
+    }
+
+    fn display_name(&self) -> String {
         "xHamster".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -169,7 +172,7 @@ impl SiteDefinition for XHamsterHandler {
         Ok("ts".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
 }
@@ -178,3 +181,45 @@ impl SiteDefinition for XHamsterHandler {
 inventory::submit! {
     &XHamsterHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_playlist_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://xhamster.com/videos/city-lights-timelapse-xhA1b2C";
+        let handler = XHamsterHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "City Lights Timelapse"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://video-nss.xhcdn.com/key=aBcD,end=1760810000/data=2a0e/media=hls4/multi=256x144:144p,426x240:240p,854x480:480p,1280x720:720p/018/123/456/720p.h264.mp4.m3u8"
+        );
+    }
+
+    #[test]
+    fn ignores_other_sites() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://vidoza.net/embed-q8w7e6r5t4y3.html";
+
+        assert!(!XHamsterHandler
+            .can_handle_url(&mut video, &agent, url, 0)
+            .unwrap());
+    }
+}
@@ -16,14 +16,15 @@
 // Yet Another Youtube Down Loader
 // - YouTube and Invidious handler -
 
-use crate::agent::{AgentBase, YaydlAgent};
-use crate::definitions::SiteDefinition;
+use crate::agent::YaydlAgent;
+use crate::definitions::{SiteDefinition, Stream, StreamPair, Subtitle, VideoMetadata};
+use crate::recording;
 use crate::VIDEO;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use regex::Regex;
-use scraper::{Html, Selector};
-use std::env;
+use serde_json::Value;
+use std::{env, fs, path::PathBuf, sync::Mutex, time::Duration};
 use url::Url;
 
 // Starting with yaydl 0.13.0, this handler uses Invidious instead
@@ -31,45 +32,413 @@ use url::Url;
 // against Google.
 
 // The environment variable YAYDL_INVIDIOUS_INSTANCE can be used to
-// define the instance to use, otherwise, yaydl defaults to this:
-const INVIDIOUS_INSTANCE: &str = "https://invidious.nerdvpn.de";
+// define the instance(s) to use, separated by commas. Otherwise, yaydl
+// tries these in this order:
+const INVIDIOUS_INSTANCES: [&str; 3] = [
+    "https://invidious.nerdvpn.de",
+    "https://inv.nadeko.net",
+    "https://yewtu.be",
+];
+
+// The instance that has worked in this run:
+static WORKING_INSTANCE: Mutex<Option<String>> = Mutex::new(None);
+
+// The instance that has worked last time is remembered in this file.
+// Replays neither use nor change it.
+fn remembered_instance_file() -> Option<PathBuf> {
+    if recording::is_replaying() {
+        return None;
+    }
+    let state_dir = env::var_os("XDG_STATE_HOME")
+        .or_else(|| env::var_os("LOCALAPPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state_dir.join("yaydl").join("invidious_instance"))
+}
+
+fn remember_instance(instance: &str) {
+    *WORKING_INSTANCE.lock().unwrap() = Some(instance.to_string());
+    if let Some(file) = remembered_instance_file() {
+        if let Some(dir) = file.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(file, instance);
+    }
+}
+
+// Returns the instances in the order they should be tried: the one that
+// has worked (in this run or the last one) comes first.
+fn get_invidious_instances() -> Vec<String> {
+    let mut instances: Vec<String> = match env::var("YAYDL_INVIDIOUS_INSTANCE") {
+        Ok(list) => list
+            .split(',')
+            .map(|instance| instance.trim().trim_end_matches('/').to_string())
+            .filter(|instance| !instance.is_empty())
+            .collect(),
+        Err(_) => INVIDIOUS_INSTANCES.iter().map(|i| i.to_string()).collect(),
+    };
+
+    let working = WORKING_INSTANCE.lock().unwrap().clone().or_else(|| {
+        remembered_instance_file()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|instance| instance.trim().to_string())
+    });
+    if let Some(pos) = working.and_then(|w| instances.iter().position(|i| *i == w)) {
+        let instance = instances.remove(pos);
+        instances.insert(0, instance);
+    }
+    instances
+}
 
 fn get_invidious_instance() -> String {
-    let invidious_env = env::var("YAYDL_INVIDIOUS_INSTANCE");
-    if invidious_env.is_ok() {
-        invidious_env.unwrap_or(INVIDIOUS_INSTANCE.to_string())
+    get_invidious_instances()
+        .into_iter()
+        .next()
+        .unwrap_or(INVIDIOUS_INSTANCES[0].to_string())
+}
+
+// A quick look whether <instance> is up at all before it is chosen.
+fn is_healthy(agent: &YaydlAgent, instance: &str) -> bool {
+    agent
+        .get(&format!("{}/api/v1/stats", instance))
+        .config()
+        .timeout_global(Some(Duration::from_secs(10)))
+        .http_status_as_error(false)
+        .build()
+        .call()
+        // Some instances do not publish their statistics, that is fine.
+        .is_ok_and(|response| !response.status().is_server_error())
+}
+
+fn get_video_id(url: &str) -> Result<String> {
+    let no_id = || anyhow!("{} has no video ID.", url);
+    let parsed = Url::parse(url).map_err(|_| no_id())?;
+
+    // youtu.be/<id> and .../shorts/<id> have it in the path, everything
+    // else in "v=":
+    let mut path = parsed.path_segments().into_iter().flatten();
+    let id = if parsed
+        .host_str()
+        .is_some_and(|host| host.ends_with("youtu.be"))
+    {
+        path.next().map(str::to_string)
+    } else if let Some(id) = path.by_ref().skip_while(|s| *s != "shorts").nth(1) {
+        Some(id.to_string())
     } else {
-        INVIDIOUS_INSTANCE.to_string()
+        parsed
+            .query_pairs()
+            .find(|(name, _)| name == "v")
+            .map(|(_, id)| id.into_owned())
+    };
+    id.filter(|id| !id.is_empty()).ok_or_else(no_id)
+}
+
+fn is_invidious_url(url: &str) -> bool {
+    Regex::new(r"invidious\.|(?:www\.)?youtu(?:be\.com|\.be)/")
+        .unwrap()
+        .is_match(url)
+}
+
+fn has_video_id(url: &str) -> bool {
+    get_video_id(url).is_ok()
+}
+
+fn get_playlist_id(url: &str) -> Option<&str> {
+    let list_regex = Regex::new(r"[?&]list=([^&#]+)").unwrap();
+    Some(list_regex.captures(url)?.get(1)?.as_str())
+}
+
+// The numeric value of a field that Invidious sometimes sends as a string.
+fn number_of(json: &Value, field: &str) -> u64 {
+    match &json[field] {
+        Value::String(s) => s.trim_end_matches('p').parse().unwrap_or(0),
+        v => v.as_u64().unwrap_or(0),
     }
 }
 
-fn get_video_info(video: &mut VIDEO, url: &str) -> Result<Html> {
-    if video.info.is_empty() {
-        // We need to fetch the video information first.
-        // It will contain the whole body for now.
-        // Exchange the URL -> Invidious:
-        let id_regex = Regex::new(r"(?:v=|\.be/|shorts/)(.*?)(&.*)*$").unwrap();
-        let id = id_regex.captures(url).unwrap().get(1).unwrap().as_str();
+fn string_of(json: &Value, field: &str) -> Option<String> {
+    json[field]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
 
-        let invidious_url = format!("{}/watch?v={}", get_invidious_instance(), id);
-        let local_url = invidious_url.to_owned();
+// Invidious returns some URLs relative to the instance.
+fn absolute_url(instance: &str, url: &str) -> String {
+    if url.starts_with("//") {
+        format!("https:{}", url)
+    } else if url.starts_with('/') {
+        format!("{}{}", instance, url)
+    } else {
+        url.to_string()
+    }
+}
 
-        // Initialize the agent:
-        let url_p = Url::parse(&local_url)?;
-        let agent = YaydlAgent::init(url_p);
+// One of the formats a video is available in.
+struct Format {
+    itag: u64,
+    // e.g. "video/mp4; codecs=\"avc1.64001F, mp4a.40.2\""
+    mime_type: String,
+    // e.g. "mp4", "webm" or "m4a"
+    container: String,
+    // The height, e.g. 720 for "720p". 0 for audio.
+    resolution: u64,
+    bitrate: u64,
+}
+
+impl Format {
+    fn from_json(json: &Value) -> Self {
+        Format {
+            itag: number_of(json, "itag"),
+            mime_type: string_of(json, "type").unwrap_or_default(),
+            container: string_of(json, "container").unwrap_or_default(),
+            resolution: number_of(json, "resolution"),
+            bitrate: number_of(json, "bitrate"),
+        }
+    }
 
-        let body = agent
-            .get(&local_url)
+    // Lets the instance proxy the stream, googlevideo.com URLs are
+    // bound to the instance's IP address.
+    fn url(&self, instance: &str, id: &str) -> String {
+        format!(
+            "{}/latest_version?id={}&itag={}&local=true",
+            instance, id, self.itag
+        )
+    }
+}
+
+// What /api/v1/videos/{id} tells about a video.
+struct InvidiousVideo {
+    title: String,
+    author: Option<String>,
+    // A Unix timestamp.
+    published: Option<i64>,
+    length_seconds: Option<u64>,
+    description: Option<String>,
+    thumbnail: Option<String>,
+    // Video and audio in one file:
+    format_streams: Vec<Format>,
+    // Video or audio only:
+    adaptive_formats: Vec<Format>,
+    captions: Vec<Subtitle>,
+}
+
+impl InvidiousVideo {
+    fn from_json(json: &Value, instance: &str) -> Self {
+        let formats = |field: &str| {
+            json[field]
+                .as_array()
+                .map(|formats| formats.iter().map(Format::from_json).collect())
+                .unwrap_or_default()
+        };
+
+        // The widest thumbnail wins:
+        let thumbnail = json["videoThumbnails"].as_array().and_then(|thumbnails| {
+            thumbnails
+                .iter()
+                .max_by_key(|t| number_of(t, "width"))
+                .and_then(|t| t["url"].as_str())
+                .map(|url| absolute_url(instance, url))
+        });
+
+        let captions = json["captions"]
+            .as_array()
+            .map(|captions| {
+                captions
+                    .iter()
+                    .filter_map(|c| {
+                        Some(Subtitle {
+                            language: string_of(c, "language_code")
+                                .or_else(|| string_of(c, "languageCode"))?,
+                            label: string_of(c, "label").unwrap_or_default(),
+                            url: absolute_url(instance, c["url"].as_str()?),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        InvidiousVideo {
+            title: string_of(json, "title").unwrap_or_default(),
+            author: string_of(json, "author"),
+            published: json["published"].as_i64().filter(|p| *p > 0),
+            length_seconds: json["lengthSeconds"].as_u64(),
+            description: string_of(json, "description"),
+            thumbnail,
+            format_streams: formats("formatStreams"),
+            adaptive_formats: formats("adaptiveFormats"),
+            captions,
+        }
+    }
+}
+
+// Finds the best format whose MIME type starts with <kind>.
+// <preferred_container> wins over a better bitrate.
+fn best_format<'a>(
+    formats: &'a [Format],
+    kind: &str,
+    preferred_container: Option<&str>,
+) -> Option<&'a Format> {
+    formats
+        .iter()
+        .filter(|f| f.mime_type.starts_with(kind))
+        .max_by_key(|f| {
+            (
+                preferred_container.is_some_and(|c| f.container == c),
+                f.resolution,
+                f.bitrate,
+            )
+        })
+}
+
+// Asks the instances for <path> (e.g. "/api/v1/videos/<id>") until one
+// answers without an error and <check> is happy with the answer.
+fn query_instances(
+    agent: &YaydlAgent,
+    path: &str,
+    check: impl Fn(&Value, &str) -> Result<()>,
+) -> Result<String> {
+    let query = |instance: &str| -> Result<String> {
+        // The instance that is in use has been checked already; if it
+        // stops working, the request below fails and the next one is tried.
+        let in_use = WORKING_INSTANCE.lock().unwrap().as_deref() == Some(instance);
+        if !in_use && !is_healthy(agent, instance) {
+            return Err(anyhow!("{} does not respond.", instance));
+        }
+
+        let body = agent.get_page(&format!("{}{}", instance, path))?;
+        let json: Value = serde_json::from_str(&body)?;
+        if let Some(error) = json["error"].as_str() {
+            return Err(anyhow!("{} says: \"{}\".", instance, error));
+        }
+        check(&json, instance)?;
+        Ok(body)
+    };
+
+    let mut last_error = anyhow!("There is no Invidious instance to use.");
+    let instances = get_invidious_instances();
+    for (num, instance) in instances.iter().enumerate() {
+        match query(instance) {
+            Ok(body) => {
+                remember_instance(instance);
+                return Ok(body);
+            }
+            Err(e) => {
+                if num + 1 < instances.len() {
+                    println!("{} Trying the next instance.", e);
+                }
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+// Finds the first of <instances> that can stream all <formats> of the
+// video <id>. Instances can answer for a video, but still fail to fetch
+// it from YouTube.
+fn find_streaming_instance(
+    agent: &YaydlAgent,
+    instances: &[String],
+    id: &str,
+    formats: &[&Format],
+) -> Result<String> {
+    let can_stream = |instance: &str, format: &Format| -> Result<()> {
+        // The first byte is enough to know:
+        agent
+            .get(&format.url(instance, id))
+            .header("Range", "bytes=0-0")
+            .config()
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build()
             .call()
-            .expect("Could not go to the url")
-            .body_mut()
-            .read_to_string()
-            .expect("Could not read the site source");
+            .map_err(|e| anyhow!("{} cannot stream the video: {}.", instance, e))?;
+        Ok(())
+    };
+
+    let mut last_error = anyhow!("There is no Invidious instance to use.");
+    for (num, instance) in instances.iter().enumerate() {
+        match formats
+            .iter()
+            .try_for_each(|format| can_stream(instance, format))
+        {
+            Ok(()) => return Ok(instance.to_string()),
+            Err(e) => {
+                if num + 1 < instances.len() {
+                    println!("{} Trying the next instance.", e);
+                }
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+// The instance to download the <formats> of the video <id> from.
+fn get_streaming_instance(agent: &YaydlAgent, id: &str, formats: &[&Format]) -> Result<String> {
+    let instance = find_streaming_instance(agent, &get_invidious_instances(), id, formats)?;
+    remember_instance(&instance);
+    Ok(instance)
+}
+
+fn get_video_info(video: &mut VIDEO, agent: &YaydlAgent, url: &str) -> Result<InvidiousVideo> {
+    if video.info.is_empty() {
+        // We need to fetch the video information first.
+        // Exchange the URL -> Invidious. Answers without any streams
+        // are as good as errors:
+        let id = get_video_id(url)?;
+        let body = query_instances(
+            agent,
+            &format!("/api/v1/videos/{}", id),
+            |json, instance| {
+                let video_info = InvidiousVideo::from_json(json, instance);
+                if video_info.title.is_empty()
+                    || (video_info.format_streams.is_empty()
+                        && video_info.adaptive_formats.is_empty())
+                {
+                    return Err(anyhow!("{} did not return any streams.", instance));
+                }
+                Ok(())
+            },
+        )?;
         video.info.push_str(&body);
     }
 
-    let d = Html::parse_document(&video.info);
-    Ok(d)
+    let json: Value = serde_json::from_str(&video.info)?;
+    Ok(InvidiousVideo::from_json(&json, &get_invidious_instance()))
+}
+
+// Returns the IDs of the videos in the playlist <list_id>. Invidious
+// sends them page by page.
+fn get_playlist_video_ids(agent: &YaydlAgent, list_id: &str) -> Result<Vec<String>> {
+    let mut ids: Vec<String> = vec![];
+    for page in 1.. {
+        let path = format!("/api/v1/playlists/{}?page={}", list_id, page);
+        let body = query_instances(agent, &path, |json, instance| {
+            if !json["videos"].is_array() {
+                return Err(anyhow!("{} did not return the playlist.", instance));
+            }
+            Ok(())
+        })?;
+        let json: Value = serde_json::from_str(&body)?;
+
+        // The last page is repeated when there are no more videos.
+        let mut new_videos = 0;
+        for video in json["videos"].as_array().into_iter().flatten() {
+            if let Some(id) = video["videoId"].as_str() {
+                if !ids.iter().any(|known| known == id) {
+                    ids.push(id.to_string());
+                    new_videos += 1;
+                }
+            }
+        }
+        if new_videos == 0 {
+            break;
+        }
+    }
+    Ok(ids)
 }
 
 // Implement the site definition:
@@ -78,110 +447,169 @@ impl SiteDefinition for YouTubeHandler {
     fn can_handle_url<'a>(
         &'a self,
         _video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        Ok(Regex::new(r"invidious\.|(?:www\.)?youtu(?:be\.com|\.be)/")
-            .unwrap()
-            .is_match(url))
+        Ok(is_invidious_url(url))
     }
 
     fn is_playlist<'a>(&'a self, _url: &'a str, _webdriver_port: u16) -> Result<bool> {
-        // Left as an exercise to the user. TBD.
+        // The streams are single files. Video playlists are handled by
+        // find_playlist_entries().
         Ok(false)
     }
 
+    fn find_playlist_entries<'a>(
+        &'a self,
+        agent: &YaydlAgent,
+        url: &'a str,
+        no_playlist: bool,
+    ) -> Result<Option<Vec<String>>> {
+        if !is_invidious_url(url) {
+            return Ok(None);
+        }
+        let Some(list_id) = get_playlist_id(url) else {
+            return Ok(None);
+        };
+
+        let has_video = has_video_id(url);
+        if has_video && no_playlist {
+            return Ok(None);
+        }
+
+        match get_playlist_video_ids(agent, list_id) {
+            Ok(ids) => Ok(Some(
+                ids.iter()
+                    .map(|id| format!("https://www.youtube.com/watch?v={}", id))
+                    .collect(),
+            )),
+            // Mixes and the like cannot be fetched as playlists, but the
+            // video itself still works:
+            Err(e) if has_video => {
+                println!("{} Only downloading the video.", e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     fn find_video_title<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<String> {
-        let video_info = get_video_info(video, url)?;
-
-        let title_selector = Selector::parse(r#"meta[property="og:title"]"#).unwrap();
-        let title_elem = video_info.select(&title_selector).next().unwrap();
-        let title_contents = title_elem.value().attr("content").unwrap();
-
-        Ok(title_contents.to_string())
+        Ok(get_video_info(video, agent, url)?.title)
     }
 
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
     ) -> Result<String> {
-        let video_info = get_video_info(video, url)?;
-
-        let mut url_to_choose = "".to_string();
-
-        // Find the least horrible format:
-        let quality_selector = Selector::parse(r#"source"#).unwrap();
-        let quality_iter = video_info.select(&quality_selector);
-
-        let mut last_vq: String = String::new();
-
-        for source in quality_iter {
-            // The highest quality wins.
-            let this_tag = source;
-            let this_vq = this_tag.value().attr("label").unwrap_or("");
-
-            let is_same_or_better_video = this_vq != last_vq && last_vq != "medium";
-
-            // Try to download the best file.
-            if is_same_or_better_video {
-                let this_mimetype = this_tag.value().attr("type").unwrap();
-
-                // Example: type="video/mp4; codecs=&quot;avc1.64001F, mp4a.40.2&quot;"
-                // Fetch the video/mp4 substring:
-                let mut mime_split = this_mimetype.split(";");
-                video.mime = mime_split.next().unwrap().to_string();
-
-                let relative_url = this_tag.value().attr("src").unwrap();
-                url_to_choose = relative_url.to_string();
-
-                // Only update last_vq if it's the best format yet.
-                last_vq = String::from(this_vq);
-            }
-        }
-
-        if url_to_choose.is_empty() {
-            Err(anyhow::Error::msg(
-                "Could not find a working video - aborting.".to_string(),
-            ))
-        } else {
-            // Check whether we have a redirector in place.
-            let url_p = Url::parse(&url_to_choose)?;
-            let agent = YaydlAgent::init(url_p);
-            let resp = agent.get(&url_to_choose).call()?;
-            let has_redirect = resp.headers().get("Location");
-            if has_redirect.is_some() {
-                url_to_choose = has_redirect.unwrap().to_str()?.to_string();
-            }
-
-            Ok(url_to_choose)
-        }
+        let video_info = get_video_info(video, agent, url)?;
+
+        // The highest quality wins.
+        let format = best_format(&video_info.format_streams, "video/", None)
+            .ok_or_else(|| anyhow!("Could not find a working video - aborting."))?;
+
+        // Example: type="video/mp4; codecs=&quot;avc1.64001F, mp4a.40.2&quot;"
+        // Fetch the video/mp4 substring:
+        video.mime = format
+            .mime_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        let id = get_video_id(url)?;
+        let instance = get_streaming_instance(agent, &id, &[format])?;
+        Ok(format.url(&instance, &id))
     }
 
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
     ) -> Result<bool> {
-        let _video_info = get_video_info(video, url);
-        Ok(!video.info.is_empty())
+        Ok(get_video_info(video, agent, url).is_ok_and(|info| !info.title.is_empty()))
+    }
+
+    fn find_video_metadata<'a>(
+        &'a self,
+        video: &'a mut VIDEO,
+        agent: &YaydlAgent,
+        url: &'a str,
+        _webdriver_port: u16,
+    ) -> Result<VideoMetadata> {
+        let video_info = get_video_info(video, agent, url)?;
+
+        Ok(VideoMetadata {
+            uploader: video_info.author,
+            upload_date: video_info.published.map(VideoMetadata::date_from_timestamp),
+            description: video_info.description,
+            thumbnail: video_info.thumbnail,
+            duration: video_info.length_seconds,
+            subtitles: video_info.captions,
+        })
+    }
+
+    fn find_stream_pair<'a>(
+        &'a self,
+        video: &'a mut VIDEO,
+        agent: &YaydlAgent,
+        url: &'a str,
+        _webdriver_port: u16,
+    ) -> Result<Option<StreamPair>> {
+        // The best qualities are only available as separate streams.
+        let video_info = get_video_info(video, agent, url)?;
+        let formats = &video_info.adaptive_formats;
+
+        let Some(video_format) = best_format(formats, "video/", None) else {
+            return Ok(None);
+        };
+        let audio_container = if video_format.container == "webm" {
+            "webm"
+        } else {
+            "m4a"
+        };
+        let Some(audio_format) = best_format(formats, "audio/", Some(audio_container)) else {
+            return Ok(None);
+        };
+
+        let id = get_video_id(url)?;
+        let instance = get_streaming_instance(agent, &id, &[video_format, audio_format])?;
+        let stream = |format: &Format, default_ext: &str| Stream {
+            url: format.url(&instance, &id),
+            ext: if format.container.is_empty() {
+                default_ext.to_string()
+            } else {
+                format.container.to_string()
+            },
+            is_playlist: false,
+        };
+
+        Ok(Some(StreamPair {
+            video: stream(video_format, "mp4"),
+            audio: stream(audio_format, "m4a"),
+        }))
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn display_name(&self) -> String {
         "Invidious".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -197,7 +625,7 @@ impl SiteDefinition for YouTubeHandler {
         Ok(ext.to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         false
     }
 }
@@ -206,3 +634,179 @@ impl SiteDefinition for YouTubeHandler {
 inventory::submit! {
     &YouTubeHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_ids_in_urls() {
+        let id = |url: &str| get_video_id(url).ok();
+        assert_eq!(
+            id("https://www.youtube.com/watch?v=jNQXAC9IVRw&t=5"),
+            Some("jNQXAC9IVRw".to_string())
+        );
+        assert_eq!(
+            id("https://youtu.be/jNQXAC9IVRw"),
+            Some("jNQXAC9IVRw".to_string())
+        );
+        assert_eq!(
+            id("https://www.youtube.com/shorts/jNQXAC9IVRw"),
+            Some("jNQXAC9IVRw".to_string())
+        );
+        // Whatever follows the ID is not part of it:
+        assert_eq!(
+            id("https://youtu.be/jNQXAC9IVRw?si=AbCdEf&t=3"),
+            Some("jNQXAC9IVRw".to_string())
+        );
+        assert_eq!(
+            id("https://www.youtube.com/shorts/jNQXAC9IVRw?feature=share"),
+            Some("jNQXAC9IVRw".to_string())
+        );
+        assert_eq!(
+            id("https://youtube.com/watch?list=PL123&v=jNQXAC9IVRw#t=5"),
+            Some("jNQXAC9IVRw".to_string())
+        );
+        assert_eq!(id("https://www.youtube.com/playlist?list=PL123"), None);
+        assert_eq!(id("https://youtu.be/"), None);
+        assert!(!has_video_id("https://www.youtube.com/playlist?list=PL123"));
+
+        assert_eq!(
+            get_playlist_id("https://www.youtube.com/watch?v=jNQXAC9IVRw&list=PL123&index=2"),
+            Some("PL123")
+        );
+        assert_eq!(get_playlist_id("https://youtu.be/jNQXAC9IVRw"), None);
+    }
+
+    #[test]
+    fn finds_the_streams_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://www.youtube.com/watch?v=jNQXAC9IVRw";
+        let handler = YouTubeHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "Me at the zoo"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://invidious.nerdvpn.de/latest_version?id=jNQXAC9IVRw&itag=18&local=true"
+        );
+        assert_eq!(
+            handler
+                .find_video_file_extension(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "mp4"
+        );
+
+        // The best video goes with the audio in the same container:
+        let pair = handler
+            .find_stream_pair(&mut video, &agent, url, 0)
+            .unwrap()
+            .unwrap();
+        assert!(pair.video.url.ends_with("&itag=134&local=true"));
+        assert!(pair.audio.url.ends_with("&itag=140&local=true"));
+        assert_eq!(pair.audio.ext, "m4a");
+
+        let metadata = handler
+            .find_video_metadata(&mut video, &agent, url, 0)
+            .unwrap();
+        assert_eq!(metadata.uploader.as_deref(), Some("jawed"));
+        assert_eq!(metadata.upload_date.as_deref(), Some("2005-04-22"));
+        assert_eq!(
+            metadata.thumbnail.as_deref(),
+            Some("https://invidious.nerdvpn.de/vi/jNQXAC9IVRw/maxres.jpg")
+        );
+        assert_eq!(metadata.duration, Some(19));
+        // Captions without a language are skipped:
+        let subtitles: Vec<_> = metadata
+            .subtitles
+            .iter()
+            .map(|s| (s.language.as_str(), s.label.as_str(), s.url.as_str()))
+            .collect();
+        assert_eq!(
+            subtitles,
+            [
+                (
+                    "en",
+                    "English",
+                    "https://invidious.nerdvpn.de/api/v1/captions/jNQXAC9IVRw?label=English"
+                ),
+                (
+                    "de",
+                    "Deutsch (automatisch erzeugt)",
+                    "https://invidious.nerdvpn.de/api/v1/captions/jNQXAC9IVRw?label=Deutsch+%28automatisch+erzeugt%29"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_over_to_an_instance_that_can_stream() {
+        let agent = replay_agent();
+        let instances =
+            ["https://invidious.nerdvpn.de", "https://inv.nadeko.net"].map(String::from);
+        let format = |itag| Format {
+            itag,
+            mime_type: "video/mp4".to_string(),
+            container: "mp4".to_string(),
+            resolution: 720,
+            bitrate: 0,
+        };
+
+        assert_eq!(
+            find_streaming_instance(&agent, &instances, "jNQXAC9IVRw", &[&format(18)]).unwrap(),
+            "https://invidious.nerdvpn.de"
+        );
+        // The first instance refuses this one:
+        assert_eq!(
+            find_streaming_instance(&agent, &instances, "jNQXAC9IVRw", &[&format(22)]).unwrap(),
+            "https://inv.nadeko.net"
+        );
+        // All formats need to work on the same instance:
+        assert!(find_streaming_instance(
+            &agent,
+            &instances,
+            "jNQXAC9IVRw",
+            &[&format(18), &format(22)]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn expands_playlists_in_a_replay() {
+        let agent = replay_agent();
+        let url = "https://www.youtube.com/playlist?list=PLbpi6ZahtOH6Ar_3GPy3workdfpGt3q1H";
+
+        let entries = YouTubeHandler
+            .find_playlist_entries(&agent, url, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            entries,
+            [
+                "https://www.youtube.com/watch?v=jNQXAC9IVRw",
+                "https://www.youtube.com/watch?v=LeAltgu_pbM",
+                "https://www.youtube.com/watch?v=hPzNl6NKAG0",
+            ]
+        );
+
+        // --no-playlist only matters if there is a video, too:
+        let video_url =
+            "https://www.youtube.com/watch?v=jNQXAC9IVRw&list=PLbpi6ZahtOH6Ar_3GPy3workdfpGt3q1H";
+        assert!(YouTubeHandler
+            .find_playlist_entries(&agent, video_url, true)
+            .unwrap()
+            .is_none());
+    }
+}
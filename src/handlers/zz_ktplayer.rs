@@ -16,7 +16,9 @@
 // Yet Another Youtube Down Loader
 // - Fallback KT/KVS5 player handler -
 
-use crate::definitions::SiteDefinition;
+use crate::agent::YaydlAgent;
+use crate::definitions::{SiteDefinition, VideoMetadata};
+use crate::recording;
 
 use anyhow::Result;
 use fantoccini::ClientBuilder;
@@ -29,6 +31,12 @@ fn get_video_info(video: &mut VIDEO, url: &str, webdriver_port: u16) -> Result<b
     if video.info.is_empty() {
         // We need to fetch the video information first.
         // It will contain the whole body for now.
+        // Replays come without a web driver:
+        if recording::is_replaying() {
+            video.info = recording::replay_page(url)?;
+            return Ok(true);
+        }
+
         let local_url = url.to_owned();
 
         let rt = runtime::Builder::new_current_thread()
@@ -37,7 +45,7 @@ fn get_video_info(video: &mut VIDEO, url: &str, webdriver_port: u16) -> Result<b
             .build()
             .unwrap();
 
-        rt.block_on(async move {
+        let body = rt.block_on(async move {
             let webdriver_url = format!("http://localhost:{}", webdriver_port);
             let c = ClientBuilder::native()
                 .connect(&webdriver_url)
@@ -52,20 +60,20 @@ fn get_video_info(video: &mut VIDEO, url: &str, webdriver_port: u16) -> Result<b
             let iframe_selector = Selector::parse(r#"iframe"#).unwrap();
             let iframe_elem = html.select(&iframe_selector).next();
 
-            match iframe_elem {
-                Some(iframe) => {
-                    // We do. Retry with the first iframe's source.
-                    c.goto(iframe.value().attr("src").unwrap())
-                        .await
-                        .expect("could not go to the URL");
-                    body = c.source().await.expect("could not read the iframe source");
-                }
-                None => (),
+            if let Some(iframe) = iframe_elem {
+                // We do. Retry with the first iframe's source.
+                c.goto(iframe.value().attr("src").unwrap())
+                    .await
+                    .expect("could not go to the URL");
+                body = c.source().await.expect("could not read the iframe source");
             };
 
-            video.info.push_str(body.as_str());
             c.close_window().await.expect("could not close the window");
+            body
         });
+
+        recording::record_page(url, &body);
+        video.info.push_str(body.as_str());
     }
 
     Ok(true)
@@ -77,6 +85,7 @@ impl SiteDefinition for ZZKTPlayerHandler {
     fn can_handle_url<'a>(
         &'a self,
         video: &mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
     ) -> Result<bool> {
@@ -100,6 +109,7 @@ impl SiteDefinition for ZZKTPlayerHandler {
     fn find_video_title<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
     ) -> Result<String> {
@@ -116,13 +126,14 @@ impl SiteDefinition for ZZKTPlayerHandler {
     fn find_video_direct_url<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
     ) -> Result<String> {
         let _ = get_video_info(video, url, _webdriver_port)?;
         let video_info_html = Html::parse_document(video.info.as_str());
-        
+
         let url_selector = Selector::parse("video").unwrap();
         let url_elem = video_info_html.select(&url_selector).next().unwrap();
         let url_contents = url_elem.value().attr("src").unwrap();
@@ -133,6 +144,7 @@ impl SiteDefinition for ZZKTPlayerHandler {
     fn does_video_exist<'a>(
         &'a self,
         video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         url: &'a str,
         webdriver_port: u16,
     ) -> Result<bool> {
@@ -140,13 +152,25 @@ impl SiteDefinition for ZZKTPlayerHandler {
         Ok(!video.info.is_empty())
     }
 
-    fn display_name<'a>(&'a self) -> String {
+    fn find_video_metadata<'a>(
+        &'a self,
+        video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
+        url: &'a str,
+        webdriver_port: u16,
+    ) -> Result<VideoMetadata> {
+        let _ = get_video_info(video, url, webdriver_port)?;
+        Ok(VideoMetadata::from_html(&Html::parse_document(&video.info)))
+    }
+
+    fn display_name(&self) -> String {
         "Generic KT player".to_string()
     }
 
     fn find_video_file_extension<'a>(
         &'a self,
         _video: &'a mut VIDEO,
+        _agent: &YaydlAgent,
         _url: &'a str,
         _webdriver_port: u16,
         _onlyaudio: bool,
@@ -154,7 +178,7 @@ impl SiteDefinition for ZZKTPlayerHandler {
         Ok("mp4".to_string())
     }
 
-    fn web_driver_required<'a>(&'a self) -> bool {
+    fn web_driver_required(&self) -> bool {
         true
     }
 }
@@ -163,3 +187,34 @@ impl SiteDefinition for ZZKTPlayerHandler {
 inventory::submit! {
     &ZZKTPlayerHandler as &dyn SiteDefinition
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{empty_video, replay_agent};
+
+    #[test]
+    fn finds_the_video_in_a_replay() {
+        let agent = replay_agent();
+        let mut video = empty_video();
+        let url = "https://www.sampletube.example/videos/48211/summer-rain/";
+        let handler = ZZKTPlayerHandler;
+
+        assert!(handler.can_handle_url(&mut video, &agent, url, 0).unwrap());
+        assert!(handler
+            .does_video_exist(&mut video, &agent, url, 0)
+            .unwrap());
+        assert_eq!(
+            handler
+                .find_video_title(&mut video, &agent, url, 0)
+                .unwrap(),
+            "Summer Rain"
+        );
+        assert_eq!(
+            handler
+                .find_video_direct_url(&mut video, &agent, url, 0, false)
+                .unwrap(),
+            "https://www.sampletube.example/get_file/1/0b9c3d2e/48000/48211/48211.mp4/?br=1200"
+        );
+    }
+}
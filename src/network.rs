@@ -0,0 +1,174 @@
+/*
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.0 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * http://www.opensource.org/licenses/cddl1.txt
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ */
+
+// Yet Another Youtube Down Loader
+// - network.rs file -
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+};
+use ureq::{
+    config::IpFamily,
+    unversioned::transport::{
+        Buffers, ConnectProxyConnector, ConnectionDetails, Connector, Either, LazyBuffers,
+        NextTimeout, RustlsConnector, SocksConnector, Transport,
+    },
+    Timeout,
+};
+
+// Returns the IP family for --force-ipv4, --force-ipv6 and
+// --source-address. The source address implies its own family.
+pub fn ip_family(force_ipv4: bool, force_ipv6: bool, source_address: Option<IpAddr>) -> IpFamily {
+    match source_address {
+        _ if force_ipv4 => IpFamily::Ipv4Only,
+        _ if force_ipv6 => IpFamily::Ipv6Only,
+        Some(IpAddr::V4(_)) => IpFamily::Ipv4Only,
+        Some(IpAddr::V6(_)) => IpFamily::Ipv6Only,
+        None => IpFamily::Any,
+    }
+}
+
+// ureq's default connector chain, but the TCP connections are made
+// from <source_address>. SOCKS proxies open their own connections,
+// so they are not covered.
+pub fn connector_from(source_address: IpAddr) -> impl Connector<Out = impl Transport> {
+    ().chain(SocksConnector::default())
+        .chain(ConnectProxyConnector::default())
+        .chain(SourceAddressConnector(source_address))
+        .chain(RustlsConnector::default())
+}
+
+// Opens TCP connections from a fixed local address.
+#[derive(Debug)]
+struct SourceAddressConnector(IpAddr);
+
+impl<In: Transport> Connector<In> for SourceAddressConnector {
+    type Out = Either<In, BoundTcpTransport>;
+
+    fn connect(
+        &self,
+        details: &ConnectionDetails,
+        chained: Option<In>,
+    ) -> Result<Option<Self::Out>, ureq::Error> {
+        if chained.is_some() {
+            // Someone else (a SOCKS proxy) has connected already.
+            return Ok(chained.map(Either::A));
+        }
+
+        let mut last_error = ureq::Error::Io(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!(
+                "{} has no address to connect to from {}.",
+                details.uri, self.0
+            ),
+        ));
+
+        // Only addresses of the source address' family can be reached.
+        let addrs = details
+            .addrs
+            .iter()
+            .filter(|addr| addr.is_ipv4() == self.0.is_ipv4());
+        for addr in addrs {
+            match self.connect_to(*addr, details) {
+                Ok(stream) => {
+                    let config = details.config;
+                    let buffers =
+                        LazyBuffers::new(config.input_buffer_size(), config.output_buffer_size());
+                    return Ok(Some(Either::B(BoundTcpTransport { stream, buffers })));
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    last_error = ureq::Error::Timeout(Timeout::Connect);
+                }
+                Err(e) => last_error = ureq::Error::Io(e),
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+impl SourceAddressConnector {
+    fn connect_to(&self, addr: SocketAddr, details: &ConnectionDetails) -> io::Result<TcpStream> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.bind(&SocketAddr::new(self.0, 0).into())?;
+
+        match details.timeout.not_zero() {
+            Some(timeout) => socket.connect_timeout(&addr.into(), *timeout)?,
+            None => socket.connect(&addr.into())?,
+        }
+        if details.config.no_delay() {
+            socket.set_tcp_nodelay(true)?;
+        }
+
+        Ok(socket.into())
+    }
+}
+
+// ureq does not export its own TCP transport, so this is a plain one.
+#[derive(Debug)]
+struct BoundTcpTransport {
+    stream: TcpStream,
+    buffers: LazyBuffers,
+}
+
+// Maps socket timeouts to ureq's timeout errors.
+fn to_ureq_error(e: io::Error, timeout: &NextTimeout) -> ureq::Error {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ureq::Error::Timeout(timeout.reason),
+        _ => ureq::Error::Io(e),
+    }
+}
+
+impl Transport for BoundTcpTransport {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        &mut self.buffers
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), ureq::Error> {
+        self.stream
+            .set_write_timeout(timeout.not_zero().map(|t| *t))?;
+        let output = &self.buffers.output()[..amount];
+        self.stream
+            .write_all(output)
+            .map_err(|e| to_ureq_error(e, &timeout))
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, ureq::Error> {
+        self.stream
+            .set_read_timeout(timeout.not_zero().map(|t| *t))?;
+        let input = self.buffers.input_append_buf();
+        let amount = self
+            .stream
+            .read(input)
+            .map_err(|e| to_ureq_error(e, &timeout))?;
+        self.buffers.input_appended(amount);
+        Ok(amount > 0)
+    }
+
+    fn is_open(&mut self) -> bool {
+        // A pooled connection is still usable if there is nothing
+        // to read without blocking.
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let open = matches!(
+            self.stream.read(&mut [0]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock
+        );
+        open && self.stream.set_nonblocking(false).is_ok()
+    }
+}
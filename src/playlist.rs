@@ -0,0 +1,103 @@
+/*
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.0 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * http://www.opensource.org/licenses/cddl1.txt
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ */
+
+// Yet Another Youtube Down Loader
+// - playlist.rs file -
+
+// The videos of a playlist to download (as in --playlist-items),
+// 1-based ranges. An open range goes until the end.
+#[derive(Clone)]
+pub struct PlaylistItems(Vec<(usize, Option<usize>)>);
+
+impl PlaylistItems {
+    fn contains(&self, num: usize) -> bool {
+        self.0
+            .iter()
+            .any(|(first, last)| num >= *first && last.is_none_or(|last| num <= last))
+    }
+}
+
+// Parses something like "1-5,9,12-".
+pub fn parse_items(items: &str) -> Result<PlaylistItems, String> {
+    let invalid = || {
+        format!(
+            "\"{}\" is not a valid list of items, use something like \"1-5,9\".",
+            items
+        )
+    };
+    let number = |n: &str| n.trim().parse::<usize>().ok().filter(|n| *n > 0);
+
+    let mut ranges = vec![];
+    for item in items.split(',') {
+        let range = match item.split_once('-') {
+            Some((first, "")) => (number(first).ok_or_else(invalid)?, None),
+            Some((first, last)) => {
+                let first = number(first).ok_or_else(invalid)?;
+                let last = number(last).filter(|last| *last >= first);
+                (first, Some(last.ok_or_else(invalid)?))
+            }
+            None => {
+                let num = number(item).ok_or_else(invalid)?;
+                (num, Some(num))
+            }
+        };
+        ranges.push(range);
+    }
+    Ok(PlaylistItems(ranges))
+}
+
+// Returns the <entries> to download, in playlist order (or reversed).
+pub fn select_entries(
+    entries: Vec<String>,
+    items: Option<&PlaylistItems>,
+    reverse: bool,
+) -> Vec<String> {
+    let mut selected: Vec<String> = entries
+        .into_iter()
+        .enumerate()
+        .filter(|(num, _)| items.is_none_or(|items| items.contains(num + 1)))
+        .map(|(_, entry)| entry)
+        .collect();
+    if reverse {
+        selected.reverse();
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_items() {
+        let items = parse_items("1-3, 7,12-").unwrap();
+        assert_eq!(items.0, [(1, Some(3)), (7, Some(7)), (12, None)]);
+        let selected: Vec<usize> = (1..=15).filter(|num| items.contains(*num)).collect();
+        assert_eq!(selected, [1, 2, 3, 7, 12, 13, 14, 15]);
+
+        for items in ["", "0", "3-1", "a-b", "-5", "1,,2", "1-2-3"] {
+            assert!(parse_items(items).is_err(), "{}", items);
+        }
+    }
+
+    #[test]
+    fn selects_entries() {
+        let entries = || ["a", "b", "c", "d"].map(String::from).to_vec();
+        let items = parse_items("2-3").unwrap();
+        assert_eq!(select_entries(entries(), Some(&items), false), ["b", "c"]);
+        assert_eq!(select_entries(entries(), Some(&items), true), ["c", "b"]);
+        assert_eq!(select_entries(entries(), None, true), ["d", "c", "b", "a"]);
+    }
+}
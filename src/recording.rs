@@ -0,0 +1,301 @@
+/*
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.0 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * http://www.opensource.org/licenses/cddl1.txt
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ */
+
+// Yet Another Youtube Down Loader
+// - recording.rs file -
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+use ureq::{
+    http::{header, Request, Response},
+    middleware::{Middleware, MiddlewareNext},
+    Body, SendBody,
+};
+
+use crate::cache::fnv1a;
+
+// --record stores every HTTP exchange (and every page a web driver has
+// rendered) in a directory, --replay serves them from there without
+// touching the network. That way, handlers can be tested offline.
+//
+// Every exchange is stored as two files:
+//   <hash>-<n>.json  the request method and URL, the status and the headers
+//   <hash>-<n>.body  the response body
+// Media files (videos, audio, stream segments) only get the .json file.
+// <hash> is the hash of the method and the URL, <n> counts the requests
+// to the same URL, so they can be replayed in the same order.
+pub enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+// How often every URL has been requested so far.
+static COUNTERS: OnceLock<Mutex<HashMap<String, usize>>> = OnceLock::new();
+
+// The method for pages rendered by a web driver.
+const WEBDRIVER: &str = "WEBDRIVER";
+
+pub fn set_mode(mode: Mode) -> Result<()> {
+    if let Mode::Record(dir) = &mode {
+        fs::create_dir_all(dir)?;
+    }
+    let _ = MODE.set(mode);
+    Ok(())
+}
+
+pub fn is_recording() -> bool {
+    matches!(MODE.get(), Some(Mode::Record(_)))
+}
+
+pub fn is_replaying() -> bool {
+    matches!(MODE.get(), Some(Mode::Replay(_)))
+}
+
+// Returns the base file name of the next exchange for <method> <url>.
+fn next_file(dir: &Path, method: &str, url: &str) -> PathBuf {
+    let key = format!("{} {}", method, url);
+    let mut counters = COUNTERS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+    let count = counters.entry(key.clone()).or_insert(0);
+    *count += 1;
+
+    let file_num = |n: usize| dir.join(format!("{:016x}-{}", fnv1a(key.as_bytes()), n));
+    if is_replaying() {
+        // If we are asked more often than during the recording, replay
+        // the last exchange again.
+        let mut n = *count;
+        while n > 1 && !file_num(n).with_extension("json").exists() {
+            n -= 1;
+        }
+        return file_num(n);
+    }
+    file_num(*count)
+}
+
+fn save(file: &Path, meta: Value, body: Option<&[u8]>) -> io::Result<()> {
+    fs::write(file.with_extension("json"), meta.to_string())?;
+    match body {
+        Some(body) => fs::write(file.with_extension("body"), body),
+        None => Ok(()),
+    }
+}
+
+// For web driver handlers: stores the page source that was rendered for <url>.
+pub fn record_page(url: &str, source: &str) {
+    if let Some(Mode::Record(dir)) = MODE.get() {
+        let file = next_file(dir, WEBDRIVER, url);
+        let meta = json!({ "method": WEBDRIVER, "url": url });
+        if let Err(e) = save(&file, meta, Some(source.as_bytes())) {
+            eprintln!("Could not record {}: {}", url, e);
+        }
+    }
+}
+
+// For web driver handlers: returns the recorded page source for <url>.
+pub fn replay_page(url: &str) -> Result<String> {
+    let Some(Mode::Replay(dir)) = MODE.get() else {
+        return Err(anyhow!("Not replaying."));
+    };
+    let file = next_file(dir, WEBDRIVER, url);
+    fs::read_to_string(file.with_extension("body"))
+        .map_err(|_| anyhow!("{} has not been recorded.", url))
+}
+
+pub struct RecordingMiddleware;
+impl Middleware for RecordingMiddleware {
+    fn handle(
+        &self,
+        request: Request<SendBody>,
+        next: MiddlewareNext,
+    ) -> Result<Response<Body>, ureq::Error> {
+        let method = request.method().to_string();
+        let url = request.uri().to_string();
+
+        match MODE.get() {
+            Some(Mode::Record(dir)) => {
+                let file = next_file(dir, &method, &url);
+                match next.handle(request) {
+                    Ok(response) if is_media(&response) => {
+                        // Only remember that the file was there:
+                        let headers: Vec<(String, String)> = response
+                            .headers()
+                            .iter()
+                            .filter_map(|(k, v)| {
+                                Some((k.to_string(), v.to_str().ok()?.to_string()))
+                            })
+                            .collect();
+                        let meta = json!({
+                            "method": method,
+                            "url": url,
+                            "status": response.status().as_u16(),
+                            "headers": headers,
+                            "media": true,
+                        });
+                        save(&file, meta, None)?;
+                        Ok(response)
+                    }
+                    Ok(response) => {
+                        let (parts, body) = response.into_parts();
+
+                        // The whole body needs to be read to store it.
+                        let data = body.into_with_config().limit(u64::MAX).read_to_vec()?;
+                        // ureq has decoded the body already, so its encoding
+                        // and length are gone.
+                        let headers: Vec<(String, String)> = parts
+                            .headers
+                            .iter()
+                            .filter(|(k, _)| {
+                                *k != header::CONTENT_ENCODING && *k != header::CONTENT_LENGTH
+                            })
+                            .filter_map(|(k, v)| {
+                                Some((k.to_string(), v.to_str().ok()?.to_string()))
+                            })
+                            .collect();
+                        let meta = json!({
+                            "method": method,
+                            "url": url,
+                            "status": parts.status.as_u16(),
+                            "headers": headers,
+                        });
+                        save(&file, meta, Some(&data))?;
+
+                        Ok(Response::from_parts(parts, to_body(&headers, data)))
+                    }
+                    Err(ureq::Error::StatusCode(status)) => {
+                        let meta = json!({
+                            "method": method,
+                            "url": url,
+                            "status": status,
+                            "headers": [],
+                        });
+                        save(&file, meta, Some(&[]))?;
+                        Err(ureq::Error::StatusCode(status))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Some(Mode::Replay(dir)) => {
+                let file = next_file(dir, &method, &url);
+                let not_recorded = || {
+                    ureq::Error::Io(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} {} has not been recorded.", method, url),
+                    ))
+                };
+
+                let meta: Value = fs::read_to_string(file.with_extension("json"))
+                    .ok()
+                    .and_then(|m| serde_json::from_str(&m).ok())
+                    .ok_or_else(not_recorded)?;
+                let status = meta["status"].as_u64().unwrap_or(200) as u16;
+                if status >= 400 {
+                    return Err(ureq::Error::StatusCode(status));
+                }
+
+                let headers: Vec<(String, String)> =
+                    serde_json::from_value(meta["headers"].clone()).unwrap_or_default();
+                let mut response = Response::builder().status(status);
+                for (name, value) in &headers {
+                    response = response.header(name, value);
+                }
+
+                // Media files can be found, but not downloaded:
+                if meta["media"].as_bool() == Some(true) {
+                    let missing = MissingMedia(format!("{} has not been recorded.", url));
+                    return Ok(response.body(Body::builder().reader(missing))?);
+                }
+
+                let data = fs::read(file.with_extension("body")).map_err(|_| not_recorded())?;
+                Ok(response.body(to_body(&headers, data))?)
+            }
+            None => next.handle(request),
+        }
+    }
+}
+
+// The bodies of videos, audio files and stream segments are not recorded:
+// they can be huge and are not needed to test a handler. Their downloads
+// are streamed as usual.
+fn is_media(response: &Response<Body>) -> bool {
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    content_type.starts_with("video/")
+        || content_type.starts_with("audio/")
+        || content_type.starts_with("application/octet-stream")
+        || content_type.starts_with("binary/octet-stream")
+}
+
+// The body of a media file in a replay. Reading it fails.
+struct MissingMedia(String);
+impl io::Read for MissingMedia {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::NotFound, self.0.clone()))
+    }
+}
+
+// Builds a response body from recorded data.
+fn to_body(headers: &[(String, String)], data: Vec<u8>) -> Body {
+    let mut body = Body::builder();
+
+    let content_type = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value.as_str());
+    if let Some(content_type) = content_type {
+        let mut params = content_type.split(';').map(str::trim);
+        if let Some(mime_type) = params.next() {
+            body = body.mime_type(mime_type);
+        }
+        if let Some(charset) = params.find_map(|p| p.strip_prefix("charset=")) {
+            body = body.charset(charset);
+        }
+    }
+
+    body.data(data)
+}
+
+// The handler tests replay the exchanges in tests/fixtures. Every
+// handler asks for its own URLs, so they can share the directory.
+#[cfg(test)]
+pub fn replay_agent() -> crate::agent::YaydlAgent {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures");
+    set_mode(Mode::Replay(fixtures)).unwrap();
+    crate::agent::YaydlAgent::new(crate::agent::AgentOptions::default()).unwrap()
+}
+
+#[cfg(test)]
+pub fn empty_video() -> crate::VIDEO {
+    crate::VIDEO {
+        info: String::new(),
+        title: String::new(),
+        mime: String::new(),
+    }
+}
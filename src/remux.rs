@@ -0,0 +1,1459 @@
+/*
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.0 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * http://www.opensource.org/licenses/cddl1.txt
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ */
+
+// Yet Another Youtube Down Loader
+// - remux.rs file -
+
+// A minimal MPEG-TS demuxer and MP4 muxer for H.264 video with AAC
+// audio, which is what almost every HLS stream is made of. It is used
+// when ffmpeg is not installed. Anything else still needs ffmpeg.
+
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_AAC: u8 = 0x0f;
+// Audio and video stream types that need ffmpeg: MPEG-1/2 video,
+// MPEG audio, LATM AAC, HEVC, AC-3 and E-AC-3.
+const UNSUPPORTED_STREAM_TYPES: [u8; 8] = [0x01, 0x02, 0x03, 0x04, 0x11, 0x24, 0x81, 0x87];
+
+// PES timestamps run at 90 kHz and wrap around after 33 bits.
+const TS_TIMESCALE: u32 = 90000;
+const TS_WRAP: i64 = 1 << 33;
+const MOVIE_TIMESCALE: u32 = 1000;
+// Each AAC frame holds 1024 samples.
+const AAC_FRAME_SAMPLES: u32 = 1024;
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// Remuxes the MPEG-TS <inputfiles> (in this order) into <outputfile>.
+pub fn ts_to_mp4(inputfiles: &[PathBuf], outputfile: &Path) -> Result<()> {
+    // Same as with ffmpeg: never overwrite the input while reading it.
+    if inputfiles.iter().any(|f| f == outputfile) {
+        let mut tempfile = outputfile.to_path_buf();
+        let ext = outputfile.extension().unwrap_or_default().to_string_lossy();
+        tempfile.set_extension(format!("converting.{}", ext));
+        ts_to_mp4(inputfiles, &tempfile)?;
+        fs::rename(&tempfile, outputfile)?;
+        return Ok(());
+    }
+
+    let result = remux(inputfiles, outputfile);
+    if result.is_err() {
+        let _ = fs::remove_file(outputfile);
+    }
+    result
+}
+
+fn remux(inputfiles: &[PathBuf], outputfile: &Path) -> Result<()> {
+    let mut muxer = Mp4Writer::create(outputfile)?;
+    let mut demuxer = Demuxer::default();
+
+    for inputfile in inputfiles {
+        let mut reader = BufReader::new(fs::File::open(inputfile)?);
+        let mut packet = [0u8; TS_PACKET_SIZE];
+
+        // Every file (e.g. the segment groups of a playlist) has its
+        // own program tables and timestamps.
+        demuxer.reset();
+        muxer.start_group();
+        loop {
+            match reader.read_exact(&mut packet) {
+                Ok(()) => demuxer.push_packet(&packet, &mut muxer)?,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        demuxer.flush(&mut muxer)?;
+    }
+
+    muxer.finish()
+}
+
+// ---- MPEG-TS ----
+
+#[derive(Default)]
+struct Demuxer {
+    pmt_pid: Option<u16>,
+    video_pid: Option<u16>,
+    audio_pid: Option<u16>,
+    // The PES packets that are still being received, by PID:
+    pending: HashMap<u16, Vec<u8>>,
+}
+
+impl Demuxer {
+    fn reset(&mut self) {
+        *self = Demuxer::default();
+    }
+
+    fn push_packet(&mut self, packet: &[u8], muxer: &mut Mp4Writer) -> Result<()> {
+        if packet[0] != TS_SYNC_BYTE {
+            return Err(anyhow!("This is not an MPEG-TS stream."));
+        }
+
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from(packet[1] & 0x1f) << 8 | u16::from(packet[2]);
+        let adaptation_field = packet[3] & 0x20 != 0;
+        let has_payload = packet[3] & 0x10 != 0;
+
+        let mut start = 4;
+        if adaptation_field {
+            start += 1 + usize::from(packet[4]);
+        }
+        if !has_payload || start >= packet.len() {
+            return Ok(());
+        }
+        let payload = &packet[start..];
+
+        if pid == 0 {
+            if unit_start {
+                self.parse_pat(payload);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if unit_start {
+                self.parse_pmt(payload)?;
+            }
+        } else if Some(pid) == self.video_pid || Some(pid) == self.audio_pid {
+            if unit_start {
+                if let Some(pes) = self.pending.insert(pid, payload.to_vec()) {
+                    self.emit(pid, &pes, muxer)?;
+                }
+            } else if let Some(pes) = self.pending.get_mut(&pid) {
+                pes.extend_from_slice(payload);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Hands the incomplete PES packets to the muxer at the end of a file.
+    fn flush(&mut self, muxer: &mut Mp4Writer) -> Result<()> {
+        for pid in [self.video_pid, self.audio_pid].into_iter().flatten() {
+            if let Some(pes) = self.pending.remove(&pid) {
+                self.emit(pid, &pes, muxer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn emit(&self, pid: u16, pes: &[u8], muxer: &mut Mp4Writer) -> Result<()> {
+        let Some(pes) = Pes::parse(pes) else {
+            return Ok(());
+        };
+        if Some(pid) == self.video_pid {
+            muxer.push_video(&pes)
+        } else {
+            muxer.push_audio(&pes)
+        }
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) {
+        let Some(section) = psi_section(payload) else {
+            return;
+        };
+        // Take the first program:
+        self.pmt_pid = section
+            .get(8..)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .find(|program| program[0] != 0 || program[1] != 0)
+            .map(|program| u16::from(program[2] & 0x1f) << 8 | u16::from(program[3]));
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) -> Result<()> {
+        let Some(section) = psi_section(payload) else {
+            return Ok(());
+        };
+        if section.len() < 12 {
+            return Ok(());
+        }
+        let program_info_length = usize::from(section[10] & 0x0f) << 8 | usize::from(section[11]);
+
+        let mut i = 12 + program_info_length;
+        while i + 5 <= section.len() {
+            let stream_type = section[i];
+            let pid = u16::from(section[i + 1] & 0x1f) << 8 | u16::from(section[i + 2]);
+            let es_info_length =
+                usize::from(section[i + 3] & 0x0f) << 8 | usize::from(section[i + 4]);
+            i += 5 + es_info_length;
+
+            match stream_type {
+                STREAM_TYPE_H264 if self.video_pid.is_none() => self.video_pid = Some(pid),
+                STREAM_TYPE_AAC if self.audio_pid.is_none() => self.audio_pid = Some(pid),
+                t if UNSUPPORTED_STREAM_TYPES.contains(&t) => {
+                    return Err(anyhow!(
+                        "The stream has a track (type 0x{:02x}) that only ffmpeg can convert.",
+                        t
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the PSI section (without its CRC) of a PAT or PMT payload.
+fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = usize::from(*payload.first()?);
+    let section = payload.get(1 + pointer..)?;
+    let section_length = usize::from(*section.get(1)? & 0x0f) << 8 | usize::from(*section.get(2)?);
+    section.get(..(3 + section_length).checked_sub(4)?)
+}
+
+struct Pes<'a> {
+    pts: Option<i64>,
+    dts: Option<i64>,
+    payload: &'a [u8],
+}
+
+impl<'a> Pes<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.get(..3)? != [0, 0, 1] {
+            return None;
+        }
+        let flags = *data.get(7)?;
+        let header_length = usize::from(*data.get(8)?);
+        let pts = if flags & 0x80 != 0 {
+            Some(parse_timestamp(data.get(9..14)?))
+        } else {
+            None
+        };
+        let dts = if flags & 0xc0 == 0xc0 {
+            Some(parse_timestamp(data.get(14..19)?))
+        } else {
+            None
+        };
+
+        Some(Pes {
+            pts,
+            dts,
+            payload: data.get(9 + header_length..)?,
+        })
+    }
+}
+
+fn parse_timestamp(b: &[u8]) -> i64 {
+    i64::from(b[0] >> 1 & 0x07) << 30
+        | i64::from(b[1]) << 22
+        | i64::from(b[2] >> 1) << 15
+        | i64::from(b[3]) << 7
+        | i64::from(b[4] >> 1)
+}
+
+// Makes a 33-bit timestamp continuous with <last>.
+fn unwrap_timestamp(ts: i64, last: Option<i64>) -> i64 {
+    let Some(last) = last else {
+        return ts;
+    };
+    let mut ts = ts + (last - last.rem_euclid(TS_WRAP));
+    if ts - last > TS_WRAP / 2 {
+        ts -= TS_WRAP;
+    } else if last - ts > TS_WRAP / 2 {
+        ts += TS_WRAP;
+    }
+    ts
+}
+
+// ---- H.264 ----
+
+// Splits an Annex B byte stream into its NAL units.
+fn nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut units = vec![];
+    let mut start = None;
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(start) = start {
+                units.push(trim_trailing_zeros(&data[start..i]));
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(start) = start {
+        units.push(trim_trailing_zeros(&data[start..]));
+    }
+    units.retain(|unit| !unit.is_empty());
+    units
+}
+
+fn trim_trailing_zeros(unit: &[u8]) -> &[u8] {
+    let end = unit.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    &unit[..end]
+}
+
+struct BitReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl BitReader {
+    // The emulation prevention bytes (00 00 03) are removed first.
+    fn new(unit: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(unit.len());
+        let mut zeros = 0;
+        for &b in unit {
+            if zeros >= 2 && b == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if b == 0 { zeros + 1 } else { 0 };
+            data.push(b);
+        }
+        BitReader { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = byte >> (7 - self.pos % 8) & 1;
+        self.pos += 1;
+        Some(u32::from(bit))
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        (0..n).try_fold(0, |value, _| Some(value << 1 | self.bit()?))
+    }
+
+    // Exp-Golomb codes:
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let value = self.ue()?;
+        Some(if value % 2 == 1 {
+            value.div_ceil(2) as i32
+        } else {
+            -((value / 2) as i32)
+        })
+    }
+}
+
+// Returns the picture size from a sequence parameter set.
+fn sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(sps);
+    r.bits(8)?; // NAL header
+    let profile_idc = r.bits(8)?;
+    r.bits(16)?; // constraint flags, level
+    r.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    if [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135].contains(&profile_idc) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc == 3 && r.bit()? == 1 {
+            // Separate colour planes are cropped like monochrome.
+            chroma_format_idc = 0;
+        }
+        r.ue()?; // bit_depth_luma_minus8
+        r.ue()?; // bit_depth_chroma_minus8
+        r.bit()?; // qpprime_y_zero_transform_bypass_flag
+        if r.bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.bit()? == 1 {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    r.ue()?; // log2_max_frame_num_minus4
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.bit()?; // delta_pic_order_always_zero_flag
+            r.se()?; // offset_for_non_ref_pic
+            r.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.bit()?; // gaps_in_frame_num_value_allowed_flag
+
+    let width_in_mbs = r.ue()? + 1;
+    let height_in_map_units = r.ue()? + 1;
+    let frame_mbs_only = r.bit()?;
+    if frame_mbs_only == 0 {
+        r.bit()?; // mb_adaptive_frame_field_flag
+    }
+    r.bit()?; // direct_8x8_inference_flag
+
+    let mut width = width_in_mbs * 16;
+    let mut height = (2 - frame_mbs_only) * height_in_map_units * 16;
+    if r.bit()? == 1 {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let (crop_x, crop_y) = match chroma_format_idc {
+            1 => (2, 2 * (2 - frame_mbs_only)),
+            2 => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+        width = width.checked_sub((left + right) * crop_x)?;
+        height = height.checked_sub((top + bottom) * crop_y)?;
+    }
+
+    Some((width, height))
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8;
+    let mut next = 8;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+// ---- AAC ----
+
+#[derive(Clone, Copy, PartialEq)]
+struct AacConfig {
+    object_type: u8,
+    sample_rate_index: u8,
+    channels: u8,
+}
+
+impl AacConfig {
+    fn sample_rate(&self) -> u32 {
+        AAC_SAMPLE_RATES[usize::from(self.sample_rate_index)]
+    }
+
+    // The AudioSpecificConfig for the esds box:
+    fn audio_specific_config(&self) -> [u8; 2] {
+        [
+            self.object_type << 3 | self.sample_rate_index >> 1,
+            (self.sample_rate_index & 1) << 7 | self.channels << 3,
+        ]
+    }
+}
+
+// Splits ADTS data into its frames (without their headers).
+fn adts_frames(mut data: &[u8]) -> Vec<(AacConfig, &[u8])> {
+    let mut frames = vec![];
+    while data.len() >= 7 && data[0] == 0xff && data[1] & 0xf0 == 0xf0 {
+        let header_length = if data[1] & 0x01 == 1 { 7 } else { 9 };
+        let frame_length = usize::from(data[3] & 0x03) << 11
+            | usize::from(data[4]) << 3
+            | usize::from(data[5] >> 5);
+        if frame_length < header_length || frame_length > data.len() {
+            break;
+        }
+
+        let config = AacConfig {
+            object_type: (data[2] >> 6) + 1,
+            sample_rate_index: data[2] >> 2 & 0x0f,
+            channels: (data[2] & 0x01) << 2 | data[3] >> 6,
+        };
+        if usize::from(config.sample_rate_index) >= AAC_SAMPLE_RATES.len() {
+            break;
+        }
+        frames.push((config, &data[header_length..frame_length]));
+        data = &data[frame_length..];
+    }
+    frames
+}
+
+// ---- MP4 ----
+
+struct Sample {
+    offset: u64,
+    size: u32,
+    // Decoding time and composition offset, in 90 kHz:
+    dts: i64,
+    cts_offset: i64,
+    keyframe: bool,
+}
+
+struct Mp4Writer {
+    file: BufWriter<fs::File>,
+    mdat_start: u64,
+    position: u64,
+    video: Vec<Sample>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    audio: Vec<Sample>,
+    aac_config: Option<AacConfig>,
+    // The last timestamp of the current group (without the wrap):
+    last_timestamp: Option<i64>,
+    // Moves the current group's timestamps onto the output's timeline:
+    group_offset: i64,
+    new_group: bool,
+}
+
+impl Mp4Writer {
+    fn create(outputfile: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(fs::File::create(outputfile)?);
+
+        let mut ftyp = vec![];
+        ftyp.extend(b"isom");
+        ftyp.extend(512u32.to_be_bytes());
+        ftyp.extend(b"isomiso2avc1mp41");
+        let ftyp = mp4_box(b"ftyp", &ftyp);
+        file.write_all(&ftyp)?;
+
+        // The mdat box has a 64-bit size, which is filled in at the end:
+        let mdat_start = ftyp.len() as u64;
+        file.write_all(&1u32.to_be_bytes())?;
+        file.write_all(b"mdat")?;
+        file.write_all(&0u64.to_be_bytes())?;
+
+        Ok(Mp4Writer {
+            file,
+            mdat_start,
+            position: mdat_start + 16,
+            video: vec![],
+            sps: None,
+            pps: None,
+            audio: vec![],
+            aac_config: None,
+            last_timestamp: None,
+            group_offset: 0,
+            new_group: true,
+        })
+    }
+
+    // The timestamps of a new group (e.g. after a discontinuity or an
+    // ad break) have nothing to do with the ones before.
+    fn start_group(&mut self) {
+        self.new_group = true;
+        self.last_timestamp = None;
+    }
+
+    // Puts a PES timestamp onto the output's timeline: the 33-bit wrap
+    // is undone, and every group continues where the one before ended.
+    // Both tracks share the offset, so they stay in sync.
+    fn on_timeline(&mut self, ts: i64) -> i64 {
+        let ts = unwrap_timestamp(ts, self.last_timestamp);
+        self.last_timestamp = Some(ts);
+        if self.new_group {
+            self.new_group = false;
+            if let Some(end) = self.end_of_timeline() {
+                self.group_offset = end - ts;
+            }
+        }
+        ts + self.group_offset
+    }
+
+    // Where the samples so far end, in 90 kHz.
+    fn end_of_timeline(&self) -> Option<i64> {
+        let video_end = self.video.last().map(|last| {
+            let duration = match self.video.len() {
+                n if n > 1 => last.dts - self.video[n - 2].dts,
+                _ => 0,
+            };
+            last.dts
+                + if duration > 0 {
+                    duration
+                } else {
+                    i64::from(TS_TIMESCALE / 30)
+                }
+        });
+        let audio_end = self
+            .audio
+            .last()
+            .zip(self.aac_config)
+            .map(|(last, config)| {
+                last.dts
+                    + i64::from(AAC_FRAME_SAMPLES) * i64::from(TS_TIMESCALE)
+                        / i64::from(config.sample_rate())
+            });
+        video_end.into_iter().chain(audio_end).max()
+    }
+
+    fn write_sample(&mut self, data: &[&[u8]]) -> Result<(u64, u32)> {
+        let offset = self.position;
+        let mut size = 0;
+        for part in data {
+            self.file.write_all(part)?;
+            size += part.len();
+        }
+        self.position += size as u64;
+        Ok((offset, size as u32))
+    }
+
+    // Stores the first SPS or PPS and makes sure that it stays the same.
+    fn keep_parameter_set(stored: &mut Option<Vec<u8>>, unit: &[u8]) -> Result<()> {
+        match stored {
+            None => *stored = Some(unit.to_vec()),
+            Some(set) if set.as_slice() != unit => {
+                return Err(anyhow!("The video format changes within the stream."));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Every PES packet of the video is one access unit.
+    fn push_video(&mut self, pes: &Pes) -> Result<()> {
+        let Some(pts) = pes.pts else {
+            return Ok(());
+        };
+        let raw_dts = pes.dts.unwrap_or(pts);
+        let dts = self.on_timeline(raw_dts);
+        let cts_offset = unwrap_timestamp(pts, Some(raw_dts)) - raw_dts;
+
+        let units = nal_units(pes.payload);
+        let mut keyframe = false;
+        let mut parts: Vec<Vec<u8>> = vec![];
+        for unit in units {
+            match unit[0] & 0x1f {
+                5 => keyframe = true,
+                // MP4 has room for one SPS and PPS only, streams that
+                // switch them (e.g. the resolution) need ffmpeg.
+                7 => Self::keep_parameter_set(&mut self.sps, unit)?,
+                8 => Self::keep_parameter_set(&mut self.pps, unit)?,
+                // Access unit delimiters are not used in MP4.
+                9 => continue,
+                _ => {}
+            }
+            parts.push((unit.len() as u32).to_be_bytes().to_vec());
+            parts.push(unit.to_vec());
+        }
+
+        // The file should start with something that can be decoded.
+        if parts.is_empty() || (self.video.is_empty() && !keyframe) {
+            return Ok(());
+        }
+
+        let parts: Vec<&[u8]> = parts.iter().map(Vec::as_slice).collect();
+        let (offset, size) = self.write_sample(&parts)?;
+        self.video.push(Sample {
+            offset,
+            size,
+            dts,
+            cts_offset,
+            keyframe,
+        });
+        Ok(())
+    }
+
+    // The audio PES packets can contain several AAC frames.
+    fn push_audio(&mut self, pes: &Pes) -> Result<()> {
+        let Some(pts) = pes.pts else {
+            return Ok(());
+        };
+        let pts = self.on_timeline(pts);
+
+        for (num, (config, frame)) in adts_frames(pes.payload).into_iter().enumerate() {
+            match self.aac_config {
+                None => self.aac_config = Some(config),
+                Some(c) if c != config => {
+                    return Err(anyhow!("The audio format changes within the stream."));
+                }
+                _ => {}
+            }
+
+            let dts = pts
+                + num as i64 * i64::from(AAC_FRAME_SAMPLES) * i64::from(TS_TIMESCALE)
+                    / i64::from(config.sample_rate());
+            let (offset, size) = self.write_sample(&[frame])?;
+            self.audio.push(Sample {
+                offset,
+                size,
+                dts,
+                cts_offset: 0,
+                keyframe: true,
+            });
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let has_video = !self.video.is_empty();
+        let has_audio = !self.audio.is_empty();
+        if !has_video && !has_audio {
+            return Err(anyhow!(
+                "The stream has no H.264 video or AAC audio, only ffmpeg can convert it."
+            ));
+        }
+
+        // Patch the size of the mdat box:
+        self.file.flush()?;
+        let mut file = self.file.into_inner()?;
+        file.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        file.write_all(&(self.position - self.mdat_start).to_be_bytes())?;
+        file.seek(SeekFrom::End(0))?;
+
+        // Both tracks start at the earliest presentation time of them.
+        let video_start = self.video.first().map(|s| s.dts + s.cts_offset);
+        let audio_start = self.audio.first().map(|s| s.dts);
+        let start = video_start
+            .into_iter()
+            .chain(audio_start)
+            .min()
+            .unwrap_or(0);
+
+        let mut traks = vec![];
+        let mut movie_duration = 0;
+        let mut track_id = 1;
+        if let Some(video_start) = video_start {
+            let sps = self
+                .sps
+                .as_ref()
+                .ok_or_else(|| anyhow!("The video stream has no SPS."))?;
+            let pps = self
+                .pps
+                .as_ref()
+                .ok_or_else(|| anyhow!("The video stream has no PPS."))?;
+            let (width, height) = sps_dimensions(sps)
+                .ok_or_else(|| anyhow!("The video stream has an invalid SPS."))?;
+
+            let durations = video_durations(&self.video);
+            let media_duration: u64 = durations.iter().map(|d| u64::from(*d)).sum();
+            let track = TrackInfo {
+                id: track_id,
+                handler: *b"vide",
+                timescale: TS_TIMESCALE,
+                media_duration,
+                delay: video_start - start,
+                media_time: self.video[0].cts_offset,
+                width,
+                height,
+            };
+            movie_duration = movie_duration.max(track.movie_duration());
+            traks.push(trak(
+                &track,
+                &avc1_entry(width, height, sps, pps),
+                &self.video,
+                &durations,
+            ));
+            track_id += 1;
+        }
+        if let (Some(audio_start), Some(config)) = (audio_start, self.aac_config) {
+            let durations = audio_durations(&self.audio, config.sample_rate());
+            let track = TrackInfo {
+                id: track_id,
+                handler: *b"soun",
+                timescale: config.sample_rate(),
+                media_duration: durations.iter().map(|d| u64::from(*d)).sum(),
+                delay: audio_start - start,
+                media_time: 0,
+                width: 0,
+                height: 0,
+            };
+            movie_duration = movie_duration.max(track.movie_duration());
+            traks.push(trak(&track, &mp4a_entry(&config), &self.audio, &durations));
+            track_id += 1;
+        }
+
+        let mut moov = mvhd(movie_duration, track_id);
+        for trak in traks {
+            moov.extend(trak);
+        }
+        file.write_all(&mp4_box(b"moov", &moov))?;
+        Ok(())
+    }
+}
+
+// The duration of each video sample, up to the next one. The last one
+// (and those with broken timestamps) repeat the one before.
+fn video_durations(samples: &[Sample]) -> Vec<u32> {
+    let mut durations = Vec::with_capacity(samples.len());
+    let mut last = TS_TIMESCALE / 30;
+    for pair in samples.windows(2) {
+        let delta = pair[1].dts - pair[0].dts;
+        if delta > 0 && delta <= i64::from(u32::MAX) {
+            last = delta as u32;
+        }
+        durations.push(last);
+    }
+    if !samples.is_empty() {
+        durations.push(last);
+    }
+    durations
+}
+
+// The duration of each audio frame in samples. That is 1024, but a frame
+// before a gap in the timestamps lasts until the next one, so the audio
+// stays in sync with the video.
+fn audio_durations(samples: &[Sample], sample_rate: u32) -> Vec<u32> {
+    let frame = i64::from(AAC_FRAME_SAMPLES);
+    let mut durations: Vec<u32> = samples
+        .windows(2)
+        .map(|pair| {
+            let delta =
+                (pair[1].dts - pair[0].dts) * i64::from(sample_rate) / i64::from(TS_TIMESCALE);
+            match u32::try_from(delta) {
+                Ok(delta) if i64::from(delta) > frame + frame / 2 => delta,
+                _ => AAC_FRAME_SAMPLES,
+            }
+        })
+        .collect();
+    if !samples.is_empty() {
+        durations.push(AAC_FRAME_SAMPLES);
+    }
+    durations
+}
+
+fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut b = Vec::with_capacity(8 + content.len());
+    b.extend((8 + content.len() as u32).to_be_bytes());
+    b.extend(kind);
+    b.extend(content);
+    b
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, content: &[u8]) -> Vec<u8> {
+    let mut c = (u32::from(version) << 24 | flags).to_be_bytes().to_vec();
+    c.extend(content);
+    mp4_box(kind, &c)
+}
+
+const MATRIX: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000];
+
+fn mvhd(duration: u64, next_track_id: u32) -> Vec<u8> {
+    let mut c = vec![];
+    c.extend(0u64.to_be_bytes()); // creation time
+    c.extend(0u64.to_be_bytes()); // modification time
+    c.extend(MOVIE_TIMESCALE.to_be_bytes());
+    c.extend(duration.to_be_bytes());
+    c.extend(0x10000u32.to_be_bytes()); // rate
+    c.extend(0x100u16.to_be_bytes()); // volume
+    c.extend([0; 10]);
+    MATRIX.iter().for_each(|m| c.extend(m.to_be_bytes()));
+    c.extend([0; 24]);
+    c.extend(next_track_id.to_be_bytes());
+    full_box(b"mvhd", 1, 0, &c)
+}
+
+struct TrackInfo {
+    id: u32,
+    handler: [u8; 4],
+    timescale: u32,
+    media_duration: u64,
+    // How much later than the movie the track starts, in 90 kHz:
+    delay: i64,
+    // Where the presentation starts in the media, in its timescale:
+    media_time: i64,
+    width: u32,
+    height: u32,
+}
+
+impl TrackInfo {
+    fn to_movie_time(value: i64, timescale: u32) -> u64 {
+        (value.max(0) as u64 * u64::from(MOVIE_TIMESCALE)) / u64::from(timescale)
+    }
+
+    fn presentation_duration(&self) -> u64 {
+        Self::to_movie_time(self.media_duration as i64 - self.media_time, self.timescale)
+    }
+
+    fn movie_duration(&self) -> u64 {
+        Self::to_movie_time(self.delay, TS_TIMESCALE) + self.presentation_duration()
+    }
+}
+
+fn trak(track: &TrackInfo, sample_entry: &[u8], samples: &[Sample], durations: &[u32]) -> Vec<u8> {
+    let is_video = &track.handler == b"vide";
+
+    let mut tkhd = vec![];
+    tkhd.extend(0u64.to_be_bytes()); // creation time
+    tkhd.extend(0u64.to_be_bytes()); // modification time
+    tkhd.extend(track.id.to_be_bytes());
+    tkhd.extend([0; 4]);
+    tkhd.extend(track.movie_duration().to_be_bytes());
+    tkhd.extend([0; 8]);
+    tkhd.extend([0; 4]); // layer, alternate group
+    tkhd.extend((if is_video { 0u16 } else { 0x100 }).to_be_bytes()); // volume
+    tkhd.extend([0; 2]);
+    MATRIX.iter().for_each(|m| tkhd.extend(m.to_be_bytes()));
+    tkhd.extend((track.width << 16).to_be_bytes());
+    tkhd.extend((track.height << 16).to_be_bytes());
+
+    // The edit list delays the track and skips the composition offset
+    // of the first video frame:
+    let mut elst = vec![];
+    let delay = TrackInfo::to_movie_time(track.delay, TS_TIMESCALE);
+    elst.extend((if delay > 0 { 2u32 } else { 1 }).to_be_bytes());
+    if delay > 0 {
+        elst.extend(delay.to_be_bytes());
+        elst.extend((-1i64).to_be_bytes());
+        elst.extend(0x10000u32.to_be_bytes());
+    }
+    elst.extend(track.presentation_duration().to_be_bytes());
+    elst.extend(track.media_time.to_be_bytes());
+    elst.extend(0x10000u32.to_be_bytes());
+    let edts = mp4_box(b"edts", &full_box(b"elst", 1, 0, &elst));
+
+    let mut mdhd = vec![];
+    mdhd.extend(0u64.to_be_bytes()); // creation time
+    mdhd.extend(0u64.to_be_bytes()); // modification time
+    mdhd.extend(track.timescale.to_be_bytes());
+    mdhd.extend(track.media_duration.to_be_bytes());
+    mdhd.extend(0x55c4u16.to_be_bytes()); // "und"
+    mdhd.extend([0; 2]);
+
+    let mut hdlr = vec![0; 4];
+    hdlr.extend(track.handler);
+    hdlr.extend([0; 12]);
+    hdlr.extend(if is_video {
+        &b"VideoHandler\0"[..]
+    } else {
+        &b"SoundHandler\0"[..]
+    });
+
+    let media_header = if is_video {
+        full_box(b"vmhd", 0, 1, &[0; 8])
+    } else {
+        full_box(b"smhd", 0, 0, &[0; 4])
+    };
+    let mut dref = 1u32.to_be_bytes().to_vec();
+    dref.extend(full_box(b"url ", 0, 1, &[]));
+    let dinf = mp4_box(b"dinf", &full_box(b"dref", 0, 0, &dref));
+
+    let mut minf = media_header;
+    minf.extend(dinf);
+    minf.extend(stbl(sample_entry, samples, durations, is_video));
+
+    let mut mdia = full_box(b"mdhd", 1, 0, &mdhd);
+    mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+    mdia.extend(mp4_box(b"minf", &minf));
+
+    let mut trak = full_box(b"tkhd", 1, 3, &tkhd);
+    trak.extend(edts);
+    trak.extend(mp4_box(b"mdia", &mdia));
+    mp4_box(b"trak", &trak)
+}
+
+fn stbl(sample_entry: &[u8], samples: &[Sample], durations: &[u32], is_video: bool) -> Vec<u8> {
+    let mut stsd = 1u32.to_be_bytes().to_vec();
+    stsd.extend(sample_entry);
+
+    // Runs of equal durations:
+    let mut runs: Vec<(u32, u32)> = vec![];
+    for &duration in durations {
+        match runs.last_mut() {
+            Some((count, d)) if *d == duration => *count += 1,
+            _ => runs.push((1, duration)),
+        }
+    }
+    let mut stts = (runs.len() as u32).to_be_bytes().to_vec();
+    runs.iter().for_each(|(count, duration)| {
+        stts.extend(count.to_be_bytes());
+        stts.extend(duration.to_be_bytes());
+    });
+
+    // One sample per chunk:
+    let mut stsc = 1u32.to_be_bytes().to_vec();
+    stsc.extend([1u32, 1, 1].iter().flat_map(|v| v.to_be_bytes()));
+
+    let mut stsz = 0u32.to_be_bytes().to_vec();
+    stsz.extend((samples.len() as u32).to_be_bytes());
+    samples
+        .iter()
+        .for_each(|s| stsz.extend(s.size.to_be_bytes()));
+
+    let mut co64 = (samples.len() as u32).to_be_bytes().to_vec();
+    samples
+        .iter()
+        .for_each(|s| co64.extend(s.offset.to_be_bytes()));
+
+    let mut stbl = full_box(b"stsd", 0, 0, &stsd);
+    stbl.extend(full_box(b"stts", 0, 0, &stts));
+
+    if samples.iter().any(|s| s.cts_offset != 0) {
+        let mut ctts = (samples.len() as u32).to_be_bytes().to_vec();
+        samples.iter().for_each(|s| {
+            ctts.extend(1u32.to_be_bytes());
+            ctts.extend((s.cts_offset as i32).to_be_bytes());
+        });
+        stbl.extend(full_box(b"ctts", 1, 0, &ctts));
+    }
+    if is_video {
+        let keyframes: Vec<u32> = (1..)
+            .zip(samples)
+            .filter(|(_, s)| s.keyframe)
+            .map(|(num, _)| num)
+            .collect();
+        let mut stss = (keyframes.len() as u32).to_be_bytes().to_vec();
+        keyframes.iter().for_each(|k| stss.extend(k.to_be_bytes()));
+        stbl.extend(full_box(b"stss", 0, 0, &stss));
+    }
+
+    stbl.extend(full_box(b"stsc", 0, 0, &stsc));
+    stbl.extend(full_box(b"stsz", 0, 0, &stsz));
+    stbl.extend(full_box(b"co64", 0, 0, &co64));
+    mp4_box(b"stbl", &stbl)
+}
+
+fn avc1_entry(width: u32, height: u32, sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let mut avcc = vec![1, sps[1], sps[2], sps[3], 0xff, 0xe1];
+    avcc.extend((sps.len() as u16).to_be_bytes());
+    avcc.extend(sps);
+    avcc.push(1);
+    avcc.extend((pps.len() as u16).to_be_bytes());
+    avcc.extend(pps);
+
+    let mut c = vec![0; 6];
+    c.extend(1u16.to_be_bytes()); // data reference index
+    c.extend([0; 16]);
+    c.extend((width as u16).to_be_bytes());
+    c.extend((height as u16).to_be_bytes());
+    c.extend(0x480000u32.to_be_bytes()); // 72 dpi
+    c.extend(0x480000u32.to_be_bytes());
+    c.extend([0; 4]);
+    c.extend(1u16.to_be_bytes()); // frame count
+    c.extend([0; 32]); // compressor name
+    c.extend(0x18u16.to_be_bytes()); // depth
+    c.extend((-1i16).to_be_bytes());
+    c.extend(mp4_box(b"avcC", &avcc));
+    mp4_box(b"avc1", &c)
+}
+
+fn mp4a_entry(config: &AacConfig) -> Vec<u8> {
+    let asc = config.audio_specific_config();
+
+    // ES_Descriptor > DecoderConfigDescriptor > DecoderSpecificInfo,
+    // plus the SLConfigDescriptor:
+    let mut decoder_config = vec![0x40, 0x15, 0, 0, 0];
+    decoder_config.extend([0; 8]); // max and average bitrate
+    decoder_config.extend([0x05, asc.len() as u8]);
+    decoder_config.extend(asc);
+    let mut es = vec![0, 0, 0];
+    es.extend([0x04, decoder_config.len() as u8]);
+    es.extend(decoder_config);
+    es.extend([0x06, 0x01, 0x02]);
+    let mut esds = vec![0x03, es.len() as u8];
+    esds.extend(es);
+
+    let mut c = vec![0; 6];
+    c.extend(1u16.to_be_bytes()); // data reference index
+    c.extend([0; 8]);
+    c.extend(u16::from(config.channels).to_be_bytes());
+    c.extend(16u16.to_be_bytes()); // sample size
+    c.extend([0; 4]);
+    // The sample rate is a 16.16 number. Higher rates than 65535 Hz do not
+    // fit, decoders take those from the esds box.
+    c.extend((config.sample_rate().min(0xffff) << 16).to_be_bytes());
+    c.extend(full_box(b"esds", 0, 0, &esds));
+    mp4_box(b"mp4a", &c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes Exp-Golomb codes for the SPS fixtures.
+    #[derive(Default)]
+    struct BitWriter {
+        data: Vec<u8>,
+        bits: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, n: u32, value: u32) -> &mut Self {
+            for i in (0..n).rev() {
+                if self.bits.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+                *self.data.last_mut().unwrap() |= ((value >> i & 1) as u8) << (7 - self.bits % 8);
+                self.bits += 1;
+            }
+            self
+        }
+
+        fn ue(&mut self, value: u32) -> &mut Self {
+            let zeros = 31 - (value + 1).leading_zeros();
+            self.bits(zeros, 0).bits(zeros + 1, value + 1)
+        }
+    }
+
+    // An SPS for <width_in_mbs> x <height_in_mbs> macroblocks, with
+    // <crop_bottom> cropped at the bottom.
+    fn sps(profile_idc: u32, width_in_mbs: u32, height_in_mbs: u32, crop_bottom: u32) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(8, 0x67).bits(8, profile_idc).bits(16, 0x001f).ue(0);
+        if profile_idc == 100 {
+            // 4:2:0, 8 bits, no scaling matrix:
+            w.ue(1).ue(0).ue(0).bits(1, 0).bits(1, 0);
+        }
+        w.ue(0).ue(0).ue(4).ue(2).bits(1, 0);
+        w.ue(width_in_mbs - 1)
+            .ue(height_in_mbs - 1)
+            .bits(1, 1)
+            .bits(1, 1);
+        if crop_bottom > 0 {
+            w.bits(1, 1).ue(0).ue(0).ue(0).ue(crop_bottom);
+        } else {
+            w.bits(1, 0);
+        }
+        w.bits(1, 0).bits(1, 1);
+        w.data
+    }
+
+    // An ADTS frame of AAC LC audio.
+    fn adts(sample_rate_index: u8, channels: u8, payload: &[u8]) -> Vec<u8> {
+        let length = 7 + payload.len();
+        let mut frame = vec![
+            0xff,
+            0xf1,
+            1 << 6 | sample_rate_index << 2 | channels >> 2,
+            (channels & 3) << 6 | (length >> 11) as u8,
+            (length >> 3) as u8,
+            ((length & 7) as u8) << 5 | 0x1f,
+            0xfc,
+        ];
+        frame.extend(payload);
+        frame
+    }
+
+    fn video_access_unit() -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 1];
+        data.extend(sps(66, 20, 15, 0));
+        data.extend([0, 0, 1, 0x68, 0xce, 0x38, 0x80]);
+        data.extend([0, 0, 1, 0x65, 0x88, 0x84]);
+        data
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("yaydl-remux-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn reads_exp_golomb_codes() {
+        // 1 010 011 00100: 0, 1, 2, 3
+        let data = [0b1010_0110, 0b0100_0000];
+        let mut r = BitReader::new(&data);
+        assert_eq!(
+            (r.ue(), r.ue(), r.ue(), r.ue()),
+            (Some(0), Some(1), Some(2), Some(3))
+        );
+        let mut r = BitReader::new(&data);
+        assert_eq!(
+            (r.se(), r.se(), r.se(), r.se()),
+            (Some(0), Some(1), Some(-1), Some(2))
+        );
+        assert_eq!(BitReader::new(&[0x80]).bits(9), None);
+    }
+
+    #[test]
+    fn removes_emulation_prevention_bytes() {
+        let mut r = BitReader::new(&[0, 0, 3, 1, 0, 0, 3, 3]);
+        assert_eq!(r.bits(32), Some(0x0000_0100));
+        assert_eq!(r.bits(16), Some(3));
+        assert_eq!(r.bit(), None);
+    }
+
+    #[test]
+    fn reads_the_picture_size_from_the_sps() {
+        assert_eq!(sps_dimensions(&sps(66, 20, 15, 0)), Some((320, 240)));
+        assert_eq!(sps_dimensions(&sps(100, 80, 45, 0)), Some((1280, 720)));
+        // 1088 lines, of which 8 are cropped:
+        assert_eq!(sps_dimensions(&sps(100, 120, 68, 4)), Some((1920, 1080)));
+        assert_eq!(sps_dimensions(&[0x67, 0x42]), None);
+    }
+
+    #[test]
+    fn splits_adts_frames() {
+        let mut data = adts(4, 2, &[1, 2, 3]);
+        data.extend(adts(4, 2, &[4, 5]));
+        let frames = adts_frames(&data);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].1, [1, 2, 3]);
+        assert_eq!(frames[1].1, [4, 5]);
+
+        let config = frames[0].0;
+        assert_eq!(config.object_type, 2);
+        assert_eq!(config.sample_rate(), 44100);
+        assert_eq!(config.channels, 2);
+        assert_eq!(config.audio_specific_config(), [0x12, 0x10]);
+
+        // A cut off frame is dropped:
+        assert_eq!(adts_frames(&data[..12]).len(), 1);
+        assert!(adts_frames(&[0x47, 0x40, 0, 0x10, 0, 0, 0]).is_empty());
+    }
+
+    #[test]
+    fn finds_the_psi_section() {
+        let mut payload = vec![0, 0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00];
+        payload.extend([0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(psi_section(&payload), Some(&payload[1..13]));
+
+        // With a pointer field:
+        let mut shifted = vec![2, 0xff, 0xff];
+        shifted.extend(&payload[1..]);
+        assert_eq!(psi_section(&shifted), Some(&payload[1..13]));
+
+        assert_eq!(psi_section(&payload[..10]), None);
+        assert_eq!(psi_section(&[]), None);
+    }
+
+    #[test]
+    fn unwraps_timestamps() {
+        assert_eq!(unwrap_timestamp(1234, None), 1234);
+        assert_eq!(unwrap_timestamp(2000, Some(1000)), 2000);
+        // Over the wrap and back:
+        assert_eq!(unwrap_timestamp(100, Some(TS_WRAP - 100)), TS_WRAP + 100);
+        assert_eq!(
+            unwrap_timestamp(TS_WRAP - 100, Some(TS_WRAP + 100)),
+            TS_WRAP - 100
+        );
+        assert_eq!(unwrap_timestamp(500, Some(TS_WRAP + 100)), TS_WRAP + 500);
+        // Slightly older timestamps (B-frames) stay older:
+        assert_eq!(unwrap_timestamp(900, Some(1000)), 900);
+    }
+
+    #[test]
+    fn writes_boxes() {
+        assert_eq!(mp4_box(b"free", &[1, 2]), b"\0\0\0\x0afree\x01\x02");
+        assert_eq!(
+            full_box(b"mdhd", 1, 3, &[9]),
+            b"\0\0\0\x0dmdhd\x01\0\0\x03\x09"
+        );
+
+        let mvhd = mvhd(1500, 3);
+        assert_eq!(mvhd.len(), 120);
+        assert_eq!(&mvhd[4..8], b"mvhd");
+        assert_eq!(mvhd[32..40], 1500u64.to_be_bytes());
+        assert_eq!(mvhd[116..], 3u32.to_be_bytes());
+    }
+
+    #[test]
+    fn writes_the_sample_rate_as_16_16() {
+        let rate = |sample_rate_index| {
+            let config = AacConfig {
+                object_type: 2,
+                sample_rate_index,
+                channels: 2,
+            };
+            let entry = mp4a_entry(&config);
+            u32::from_be_bytes(entry[32..36].try_into().unwrap())
+        };
+        assert_eq!(rate(3), 48000 << 16);
+        // 96 kHz does not fit:
+        assert_eq!(rate(0), 0xffff_0000);
+    }
+
+    #[test]
+    fn continues_the_timeline_after_a_discontinuity() {
+        let outputfile = temp_file("timeline.mp4");
+        let mut muxer = Mp4Writer::create(&outputfile).unwrap();
+        let video = video_access_unit();
+        let audio = adts(3, 2, &[0x21, 0x10]);
+        let push = |muxer: &mut Mp4Writer, start: i64| {
+            for i in 0..3 {
+                let pes = Pes {
+                    pts: Some(start + i * 3000),
+                    dts: None,
+                    payload: &video,
+                };
+                muxer.push_video(&pes).unwrap();
+            }
+            let pes = Pes {
+                pts: Some(start),
+                dts: None,
+                payload: &audio,
+            };
+            muxer.push_audio(&pes).unwrap();
+        };
+
+        // The second group starts somewhere else, and over the wrap:
+        push(&mut muxer, 1000);
+        muxer.start_group();
+        push(&mut muxer, TS_WRAP - 3000);
+
+        let dts = |samples: &[Sample]| samples.iter().map(|s| s.dts).collect::<Vec<_>>();
+        assert_eq!(dts(&muxer.video), [1000, 4000, 7000, 10000, 13000, 16000]);
+        assert_eq!(dts(&muxer.audio), [1000, 10000]);
+        // The first audio frame lasts until the second one:
+        assert_eq!(
+            audio_durations(&muxer.audio, 48000),
+            [4800, AAC_FRAME_SAMPLES]
+        );
+        assert_eq!(video_durations(&muxer.video), [3000; 6]);
+
+        muxer.finish().unwrap();
+        fs::remove_file(&outputfile).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_changing_video_format() {
+        let outputfile = temp_file("sps.mp4");
+        let mut muxer = Mp4Writer::create(&outputfile).unwrap();
+        let push = |muxer: &mut Mp4Writer, pts: i64, payload: &[u8]| {
+            let pes = Pes {
+                pts: Some(pts),
+                dts: None,
+                payload,
+            };
+            muxer.push_video(&pes)
+        };
+
+        // The same SPS and PPS again are fine:
+        let video = video_access_unit();
+        push(&mut muxer, 0, &video).unwrap();
+        push(&mut muxer, 3000, &video).unwrap();
+
+        // Another resolution is not:
+        let mut video = vec![0, 0, 0, 1];
+        video.extend(sps(66, 80, 45, 0));
+        video.extend([0, 0, 1, 0x68, 0xce, 0x38, 0x80]);
+        video.extend([0, 0, 1, 0x65, 0x88, 0x84]);
+        assert!(push(&mut muxer, 6000, &video).is_err());
+
+        drop(muxer);
+        fs::remove_file(&outputfile).unwrap();
+    }
+
+    // ---- an MPEG-TS stream ----
+
+    fn ts_packets(pid: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        for (i, chunk) in data.chunks(184).enumerate() {
+            let unit_start = if i == 0 { 0x40 } else { 0 };
+            out.extend([TS_SYNC_BYTE, unit_start | (pid >> 8) as u8, pid as u8]);
+            if chunk.len() == 184 {
+                out.push(0x10);
+            } else {
+                // Stuffing in the adaptation field:
+                let stuffing = 183 - chunk.len();
+                out.extend([0x30, stuffing as u8]);
+                if stuffing > 0 {
+                    out.push(0);
+                    out.extend(vec![0xff; stuffing - 1]);
+                }
+            }
+            out.extend(chunk);
+        }
+        out
+    }
+
+    fn pes(stream_id: u8, pts: i64, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0, 0, 1, stream_id, 0, 0, 0x80, 0x80, 5];
+        data.extend([
+            0x21 | (pts >> 29 & 0x0e) as u8,
+            (pts >> 22) as u8,
+            (pts >> 14 & 0xfe) as u8 | 1,
+            (pts >> 7) as u8,
+            (pts << 1 & 0xfe) as u8 | 1,
+        ]);
+        data.extend(payload);
+        data
+    }
+
+    fn ts_file(name: &str, start: i64) -> PathBuf {
+        let pat = [
+            0, 0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00, 0, 0, 0, 0,
+        ];
+        let pmt = [
+            0,
+            0x02,
+            0xb0,
+            23,
+            0,
+            1,
+            0xc1,
+            0,
+            0,
+            0xe1,
+            0x00,
+            0xf0,
+            0x00,
+            STREAM_TYPE_H264,
+            0xe1,
+            0x00,
+            0xf0,
+            0x00,
+            STREAM_TYPE_AAC,
+            0xe1,
+            0x01,
+            0xf0,
+            0x00,
+            0,
+            0,
+            0,
+            0,
+        ];
+        let mut ts = ts_packets(0, &pat);
+        ts.extend(ts_packets(0x1000, &pmt));
+        for i in 0..3 {
+            ts.extend(ts_packets(
+                0x100,
+                &pes(0xe0, start + i * 3000, &video_access_unit()),
+            ));
+        }
+        ts.extend(ts_packets(
+            0x101,
+            &pes(0xc0, start, &adts(3, 2, &[0x21; 300])),
+        ));
+
+        let file = temp_file(name);
+        fs::write(&file, ts).unwrap();
+        file
+    }
+
+    // The mvhd duration of <mp4>, in milliseconds.
+    fn movie_duration(mp4: &[u8]) -> u64 {
+        let pos = mp4.windows(4).position(|w| w == b"mvhd").unwrap();
+        u64::from_be_bytes(mp4[pos + 28..pos + 36].try_into().unwrap())
+    }
+
+    #[test]
+    fn remuxes_mpeg_ts() {
+        let inputfiles = [ts_file("first.ts", 90000), ts_file("second.ts", 50 * 90000)];
+        let outputfile = temp_file("remuxed.mp4");
+        ts_to_mp4(&inputfiles, &outputfile).unwrap();
+        let mp4 = fs::read(&outputfile).unwrap();
+
+        assert_eq!(&mp4[4..8], b"ftyp");
+        for kind in [b"mdat", b"moov", b"avc1", b"avcC", b"mp4a", b"esds"] {
+            assert!(mp4.windows(4).any(|w| w == kind));
+        }
+        // Six frames of video, not 49 seconds:
+        assert_eq!(movie_duration(&mp4), 200);
+
+        for file in inputfiles.iter().chain([&outputfile]) {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn refuses_streams_without_h264_or_aac() {
+        let inputfile = temp_file("empty.ts");
+        fs::write(&inputfile, ts_packets(0x100, &[0; 10])).unwrap();
+        let outputfile = temp_file("empty.mp4");
+        assert!(ts_to_mp4(std::slice::from_ref(&inputfile), &outputfile).is_err());
+        assert!(!outputfile.exists());
+        fs::remove_file(&inputfile).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+/*
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.0 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * http://www.opensource.org/licenses/cddl1.txt
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ */
+
+// Yet Another Youtube Down Loader
+// - throttle.rs file -
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use ureq::{
+    http::{Request, Response},
+    middleware::{Middleware, MiddlewareNext},
+    Body, SendBody,
+};
+
+// Parses the number of requests per second (as in --rate-limit).
+pub fn parse_rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!("\"{}\" is not a positive number.", rate)),
+    }
+}
+
+// Parses a number of seconds (as in --sleep-interval).
+pub fn parse_seconds(seconds: &str) -> Result<f64, String> {
+    match seconds.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        _ => Err(format!("\"{}\" is not a valid number of seconds.", seconds)),
+    }
+}
+
+// Keeps yaydl from hammering the sites:
+// - --rate-limit spaces out the requests to each host,
+// - --sleep-interval (and --max-sleep-interval) waits for a random
+//   time between two videos and between two extraction requests.
+pub struct Throttle {
+    min_request_interval: Option<Duration>,
+    next_requests: Mutex<HashMap<String, Instant>>,
+    sleep_interval: Option<(Duration, Duration)>,
+    had_video: AtomicBool,
+    had_extraction_request: AtomicBool,
+}
+
+impl Throttle {
+    pub fn new(rate_limit: Option<f64>, sleep_interval: Option<(f64, f64)>) -> Self {
+        Throttle {
+            min_request_interval: rate_limit.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            next_requests: Mutex::new(HashMap::new()),
+            sleep_interval: sleep_interval
+                .map(|(min, max)| (Duration::from_secs_f64(min), Duration::from_secs_f64(max))),
+            had_video: AtomicBool::new(false),
+            had_extraction_request: AtomicBool::new(false),
+        }
+    }
+
+    // Waits until the next request to <host> is allowed.
+    fn wait_for_host(&self, host: &str) {
+        let Some(interval) = self.min_request_interval else {
+            return;
+        };
+
+        // Reserve a time slot first, so parallel requests do not
+        // end up in the same one.
+        let now = Instant::now();
+        let slot = {
+            let mut next_requests = self.next_requests.lock().unwrap();
+            let next = next_requests.entry(host.to_string()).or_insert(now);
+            let slot = (*next).max(now);
+            *next = slot + interval;
+            slot
+        };
+        thread::sleep(slot - now);
+    }
+
+    // Sleeps before every video but the first one.
+    pub fn sleep_before_video(&self) {
+        if self.had_video.swap(true, Ordering::SeqCst) {
+            self.sleep();
+        }
+    }
+
+    // Sleeps before every extraction request but the first one.
+    pub fn sleep_before_extraction_request(&self) {
+        if self.had_extraction_request.swap(true, Ordering::SeqCst) {
+            self.sleep();
+        }
+    }
+
+    fn sleep(&self) {
+        let Some((min, max)) = self.sleep_interval else {
+            return;
+        };
+
+        // A random point in [min, max]. RandomState is seeded randomly
+        // for every instance, which is good enough for this.
+        let random = RandomState::new().build_hasher().finish();
+        let fraction = (random >> 11) as f64 / (1u64 << 53) as f64;
+        thread::sleep(min + (max.saturating_sub(min)).mul_f64(fraction));
+    }
+}
+
+pub struct RateLimitMiddleware(pub Arc<Throttle>);
+impl Middleware for RateLimitMiddleware {
+    fn handle(
+        &self,
+        request: Request<SendBody>,
+        next: MiddlewareNext,
+    ) -> Result<Response<Body>, ureq::Error> {
+        if let Some(host) = request.uri().host() {
+            self.0.wait_for_host(host);
+        }
+        next.handle(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("2"), Ok(2.0));
+        assert_eq!(parse_rate("0.5"), Ok(0.5));
+        for rate in ["0", "-1", "inf", "NaN", "fast", ""] {
+            assert!(parse_rate(rate).is_err(), "{}", rate);
+        }
+    }
+
+    #[test]
+    fn parses_seconds() {
+        assert_eq!(parse_seconds("0"), Ok(0.0));
+        assert_eq!(parse_seconds("1.5"), Ok(1.5));
+        for seconds in ["-1", "inf", "soon"] {
+            assert!(parse_seconds(seconds).is_err(), "{}", seconds);
+        }
+    }
+
+    #[test]
+    fn spaces_out_the_requests_per_host() {
+        let throttle = Throttle::new(Some(20.0), None);
+        let start = Instant::now();
+        for _ in 0..3 {
+            throttle.wait_for_host("example.com");
+        }
+        // The first request is not delayed, the other ones by 50 ms each:
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let start = Instant::now();
+        throttle.wait_for_host("example.org");
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}